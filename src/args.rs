@@ -1,38 +1,204 @@
 use structopt::StructOpt;
 
+use crate::format::Format;
+use crate::import;
+use crate::export;
+
 #[derive(StructOpt, Debug)]
 pub struct Set {
     // Setting name
     pub name: String,
     // New setting value
-    pub value: String
+    pub value: String,
+    #[structopt(long, short)]
+    /// Run the set hooks even if the file didn't change
+    pub force: bool
 }
 
 #[derive(StructOpt, Debug)]
 pub struct Get {
     /// Setting Name
-    pub name: String
+    pub name: String,
+    #[structopt(long, possible_values = Format::NAMES)]
+    /// Print the value in a machine readable format
+    pub format: Option<Format>
 }
 
 #[derive(StructOpt, Debug)]
 pub struct Load {
-    /// Path to JSON file
-    pub path: String
+    /// Profiles to apply in order, paths to JSON files or names of profiles in the profiles
+    /// directory
+    #[structopt(required = true)]
+    pub profiles: Vec<String>,
+    #[structopt(long, short)]
+    /// Run the set hooks even if no file changed
+    pub force: bool,
+    #[structopt(long)]
+    /// Print the order the values would be set in without changing anything
+    pub dry_run: bool
+}
+
+#[derive(StructOpt, Debug)]
+pub struct Dump {
+    #[structopt(long, possible_values = Format::NAMES)]
+    /// Print the values in a machine readable format instead of pretty JSON
+    pub format: Option<Format>
+}
+
+#[derive(StructOpt, Debug)]
+pub struct List {
+    #[structopt(long, possible_values = Format::NAMES)]
+    /// Print the setting names and their files in a machine readable format
+    pub format: Option<Format>
+}
+
+#[derive(StructOpt, Debug)]
+pub struct Schema {
+    #[structopt(default_value = "registry", possible_values = &["registry", "config"])]
+    /// Which file to print the JSON Schema of
+    pub kind: String
+}
+
+#[derive(StructOpt, Debug)]
+pub struct Scaffold {
+    /// Config file to generate registry entries for
+    pub file: String,
+    #[structopt(long, short)]
+    /// Prefix for the names of the generated settings
+    pub prefix: Option<String>
+}
+
+#[derive(StructOpt, Debug)]
+pub struct Import {
+    #[structopt(possible_values = import::SOURCES)]
+    /// Kind of palette to import
+    pub source: String,
+    /// Palette file, defaults to "~/.cache/wal/colors.json" for pywal
+    pub file: Option<String>,
+    #[structopt(long, short)]
+    /// JSON file mapping setting names to palette keys, or to text with "{key}" placeholders
+    pub mapping: String,
+    #[structopt(long, short)]
+    /// Write the profile to this file instead of printing it
+    pub output: Option<String>
+}
+
+#[derive(StructOpt, Debug)]
+pub struct Export {
+    #[structopt(long, short, possible_values = export::FORMATS)]
+    /// Format to export the current values in
+    pub format: String,
+    #[structopt(long, short)]
+    /// JSON file mapping setting names to the keys to export them under, only mapped settings
+    /// are exported
+    pub mapping: Option<String>,
+    #[structopt(long, short)]
+    /// Write to this file instead of printing
+    pub output: Option<String>
+}
+
+#[derive(StructOpt, Debug)]
+pub struct Render {}
+
+#[derive(StructOpt, Debug)]
+pub struct Diff {
+    /// Profile to compare from, a path or the name of a profile in the profiles directory
+    pub from: String,
+    /// Profile to compare to, compares the first profile against the live values if left out
+    pub to: Option<String>
+}
+
+#[derive(StructOpt, Debug)]
+pub struct Filter {
+    #[structopt(long, short)]
+    /// Only use profiles with this tag in their "tags" list
+    pub tag: Option<String>,
+    #[structopt(long, short)]
+    /// Only use profiles whose name matches this glob, for example "colors/*"
+    pub glob: Option<String>
 }
 
+#[derive(StructOpt, Debug)]
+pub struct Cycle {
+    #[structopt(flatten)]
+    pub filter: Filter,
+    #[structopt(long, short)]
+    /// Run the set hooks even if no file changed
+    pub force: bool
+}
+
+#[derive(StructOpt, Debug)]
+pub struct Random {
+    #[structopt(flatten)]
+    pub filter: Filter,
+    #[structopt(long, short)]
+    /// Run the set hooks even if no file changed
+    pub force: bool,
+    #[structopt(long)]
+    /// Seed for picking the profile, the same seed and profiles always pick the same one, even the current one
+    pub seed: Option<u64>
+}
+
+#[derive(StructOpt, Debug)]
+pub enum ProfileCommands {
+    /// Load the profile after the current one
+    Next(Cycle),
+    /// Load the profile before the current one
+    Prev(Cycle),
+    /// Load a random profile other than the current one, unless --seed is given
+    Random(Random),
+    /// Print the profiles of the profiles directory
+    List(Filter),
+    /// Print the profiles loaded last
+    Current
+}
+
+#[derive(StructOpt, Debug)]
+pub struct Profile {
+    #[structopt(subcommand)]
+    pub command: ProfileCommands
+}
+
+#[derive(StructOpt, Debug)]
+pub struct Daemon {
+    #[structopt(long)]
+    /// Load the profiles the schedule picks and exit, for running from a systemd timer
+    pub once: bool,
+    #[structopt(long, short)]
+    /// Run the set hooks even if no file changed
+    pub force: bool
+}
+
+#[derive(StructOpt, Debug)]
+pub struct Watch {}
+
 #[derive(StructOpt, Debug)]
 pub enum SubCommands {
     Set(Set),
     Get(Get),
     Load(Load),
-    Dump
+    Dump(Dump),
+    List(List),
+    Schema(Schema),
+    Scaffold(Scaffold),
+    Import(Import),
+    Export(Export),
+    Render(Render),
+    Diff(Diff),
+    Profile(Profile),
+    Daemon(Daemon),
+    Watch(Watch)
 } 
 
 #[derive(StructOpt, Debug)]
 pub struct Args {
+    #[structopt(long, short, number_of_values = 1)]
+    /// Path to a custom registry, can be given several times with later ones overriding earlier
+    /// ones
+    pub registry: Vec<String>,
     #[structopt(long, short)]
-    /// Path to a custom registry
-    pub registry: Option<String>,
+    /// Path to a custom config file
+    pub config: Option<String>,
     #[structopt(subcommand)]
     pub sub: SubCommands
 }
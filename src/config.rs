@@ -1,11 +1,20 @@
-/// Handles structure of config file
+//! Handles structure of config file
 
+use schemars::JsonSchema;
 use serde_derive::{Serialize, Deserialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+use crate::schedule::{Rule, Location};
+use crate::watch::Drift;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     pub hook_before_get: Option<String>,
     pub hook_after_get: Option<String>,
     pub hook_before_set: Option<String>,
-    pub hook_after_set: Option<String>
+    pub hook_after_set: Option<String>,
+    pub schedule: Option<Vec<Rule>>, // Rules for `exconman daemon`, the last one that holds wins
+    pub schedule_interval: Option<u64>, // Default: 60, seconds between checking the schedule
+    pub location: Option<Location>, // Needed for "sunrise" and "sunset" in the schedule
+    pub on_drift: Option<Drift> // Default: Log, what `exconman watch` does when a value drifts from the active profile
 }
@@ -0,0 +1,66 @@
+//! Handles reading and writing managed files in the encoding declared by a setting
+
+use schemars::JsonSchema;
+use serde_derive::{Serialize, Deserialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, JsonSchema)]
+pub enum Encoding {
+    #[default]
+    #[serde(rename = "utf-8", alias = "utf8")]
+    Utf8,
+    // Every byte maps to the char with the same code point, so this round-trips any file, even
+    // ones in an unknown 8-bit encoding.
+    #[serde(rename = "latin1", alias = "iso-8859-1")]
+    Latin1
+}
+
+impl Encoding {
+    pub fn name(&self) -> &str {
+        match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Latin1 => "latin1"
+        }
+    }
+
+    pub fn decode(&self, bytes: Vec<u8>) -> Result<String, String> {
+        match self {
+            Encoding::Utf8 => String::from_utf8(bytes).map_err(|error| {
+                format!(
+                    "File is not valid UTF-8 (invalid byte at offset {}), declare an \"encoding\" such as \"latin1\" for it",
+                    error.utf8_error().valid_up_to()
+                )
+            }),
+            Encoding::Latin1 => Ok(bytes.iter().map(|&byte| byte as char).collect())
+        }
+    }
+
+    pub fn encode(&self, text: &str) -> Result<Vec<u8>, String> {
+        match self {
+            Encoding::Utf8 => Ok(text.as_bytes().to_vec()),
+            Encoding::Latin1 => text
+                .chars()
+                .map(|c| {
+                    if (c as u32) <= 0xff {
+                        Ok(c as u8)
+                    } else {
+                        Err(format!("Character '{}' cannot be written as latin1", c))
+                    }
+                })
+                .collect()
+        }
+    }
+}
+
+// Read a file and decode it with the given encoding
+pub fn read(path: &str, encoding: Encoding) -> Result<String, String> {
+    let bytes = std::fs::read(path).map_err(|error| error.to_string())?;
+
+    encoding.decode(bytes)
+}
+
+// Encode text with the given encoding and write it to a file
+pub fn write(path: &str, text: &str, encoding: Encoding) -> Result<(), String> {
+    let bytes = encoding.encode(text)?;
+
+    std::fs::write(path, bytes).map_err(|error| error.to_string())
+}
@@ -0,0 +1,134 @@
+//! Handles exporting the current setting values for other programs to consume
+
+use std::collections::BTreeMap;
+
+use crate::util;
+use crate::format;
+use crate::functions;
+use crate::setting::Setting;
+
+pub const FORMATS: &[&str] = &["xresources", "css", "scss", "sh", "json", "toml"];
+
+// The key a setting is exported under when there's no mapping for it
+fn default_key(name: &str, format: &str) -> String {
+    match format {
+        "css" | "scss" => name.replace(['.', '_'], "-"),
+        "xresources" => format!("exconman.{}", name),
+        "sh" => format::variable_name(name),
+        _ => name.to_string()
+    }
+}
+
+fn render(values: &[(String, String)], format: &str) -> Result<String, String> {
+    let lines = |line: &dyn Fn(&str, &str) -> String| -> String {
+        values
+            .iter()
+            .map(|(key, value)| line(key, value))
+            .collect::<Vec<String>>()
+            .join("\n")
+    };
+
+    match format {
+        "xresources" => Ok(lines(&|key, value| format!("{}: {}", key, value))),
+        "css" => Ok(format!(":root {{\n{}\n}}", lines(&|key, value| format!("  --{}: {};", key, value)))),
+        "scss" => Ok(lines(&|key, value| format!("${}: {};", key, value))),
+        "sh" => Ok(lines(&|key, value| format!("export {}={}", key, format::shell_quote(value)))),
+        "toml" => {
+            let values: BTreeMap<&str, &str> = values
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_str()))
+                .collect();
+
+            toml::to_string(&values)
+                .map(|toml| toml.trim_end().to_string())
+                .map_err(|error| error.to_string())
+        }
+        _ => {
+            let values: BTreeMap<&str, &str> = values
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_str()))
+                .collect();
+
+            serde_json::to_string_pretty(&values).map_err(|error| error.to_string())
+        }
+    }
+}
+
+// Print or write the current values of the settings. With a mapping only the mapped settings are
+// exported, under the keys the mapping gives them.
+pub fn export(format: &str, mapping: Option<String>, output: Option<String>, registry: &[Setting]) {
+    let mapping: Option<BTreeMap<String, String>> = match mapping {
+        None => None,
+        Some(path) => {
+            let mapping = std::fs::read_to_string(util::expand_env_vars(&path))
+                .map_err(|error| error.to_string())
+                .and_then(|contents| serde_json::from_str(&contents).map_err(|error| error.to_string()));
+
+            match mapping {
+                Ok(mapping) => Some(mapping),
+                Err(error) => {
+                    eprintln!(
+                        "Failed to read mapping {}\"{}\"{}: {}{}{}",
+                        util::color("green", "fg"),
+                        path,
+                        util::color("white", "fg"),
+                        util::color("red", "fg"),
+                        error,
+                        util::color("white", "fg"),
+                    );
+                    return;
+                }
+            }
+        }
+    };
+
+    let names: Vec<String> = match &mapping {
+        Some(mapping) => mapping.keys().cloned().collect(),
+        None => registry.iter().map(|setting| setting.name.to_string()).collect()
+    };
+
+    let mut values: Vec<(String, String)> = functions::get_values(names, registry)
+        .into_iter()
+        .map(|(name, value)| {
+            let key = match mapping.as_ref().and_then(|mapping| mapping.get(&name)) {
+                Some(key) => key.to_string(),
+                None => default_key(&name, format)
+            };
+
+            (key, value)
+        })
+        .collect();
+
+    values.sort();
+
+    let exported = match render(&values, format) {
+        Ok(exported) => exported,
+        Err(error) => {
+            eprintln!(
+                "Failed to generate {}: {}{}{}",
+                format,
+                util::color("red", "fg"),
+                error,
+                util::color("white", "fg"),
+            );
+            return;
+        }
+    };
+
+    match output {
+        Some(output) => {
+            if let Err(error) = std::fs::write(util::expand_env_vars(&output), exported + "\n") {
+                eprintln!(
+                    "Failed to write to {}\"{}\"{}: {}{}{}",
+                    util::color("green", "fg"),
+                    output,
+                    util::color("white", "fg"),
+                    util::color("red", "fg"),
+                    error,
+                    util::color("white", "fg"),
+                );
+            }
+        }
+        None => println!("{}", exported)
+    }
+}
@@ -0,0 +1,82 @@
+//! Handles printing setting values in machine readable formats
+
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    Shell,
+    Env,
+    Tsv
+}
+
+impl Format {
+    pub const NAMES: &'static [&'static str] = &["json", "shell", "env", "tsv"];
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "json" => Ok(Format::Json),
+            "shell" => Ok(Format::Shell),
+            "env" => Ok(Format::Env),
+            "tsv" => Ok(Format::Tsv),
+            _ => Err(format!("Unknown format \"{}\", expected one of: {}", format, Format::NAMES.join(", ")))
+        }
+    }
+}
+
+// Turn a setting name into an environment variable name,
+// "bspwm.normal_border_color" becomes "EXCONMAN_BSPWM_NORMAL_BORDER_COLOR"
+pub fn variable_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect();
+
+    format!("EXCONMAN_{}", name)
+}
+
+// Quote a value so a POSIX shell reads it back verbatim
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+// Escape the characters that would break a line based format
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t")
+}
+
+// Render name/value pairs, one line per pair except for JSON which is a single object
+pub fn render(values: &[(String, String)], format: Format) -> String {
+    match format {
+        Format::Json => {
+            let values: BTreeMap<&str, &str> = values
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str()))
+                .collect();
+
+            serde_json::to_string(&values).unwrap_or_default()
+        }
+        Format::Shell => values
+            .iter()
+            .map(|(name, value)| format!("export {}={}", variable_name(name), shell_quote(value)))
+            .collect::<Vec<String>>()
+            .join("\n"),
+        Format::Env => values
+            .iter()
+            .map(|(name, value)| format!("{}={}", variable_name(name), escape(value)))
+            .collect::<Vec<String>>()
+            .join("\n"),
+        Format::Tsv => values
+            .iter()
+            .map(|(name, value)| format!("{}\t{}", escape(name), escape(value)))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
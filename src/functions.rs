@@ -1,189 +1,247 @@
-use std::collections::HashMap;
+use std::path::PathBuf;
 
 use regex::Regex;
 
 use crate::util;
-use crate::setting::{Setting, Pattern, Replace};
+use crate::encoding;
+use crate::setting::{Setting, Matcher, Replace};
 use crate::config::Config;
+use crate::registry::{self, Registry};
+use crate::template;
+use crate::profile::{self, Profile};
+use crate::format::{self, Format};
+
+// The path of the config file from `--config`, then $EXCONMAN_CONFIG, then the config directory,
+// and whether it was asked for
+pub fn config_path(config: Option<String>) -> (String, bool) {
+    match config.or_else(|| std::env::var("EXCONMAN_CONFIG").ok()) {
+        Some(config_path) => (util::expand_env_vars(&config_path), true),
+        None => (format!("{}/config.json", util::config_dir()), false)
+    }
+}
 
-// Get the config file, if it exists
-pub fn get_config() -> Option<Config> {
-    let config_metadata = std::fs::metadata(util::expand_env_vars("~/.config/exconman/config.json"));
-
-    if config_metadata.is_ok() {
-        let config_metadata = config_metadata.unwrap();
+// Get the config file, if it exists. Only a missing default config is fine, a path that was asked
+// for has to exist.
+pub fn get_config(config: Option<String>) -> Result<Option<Config>, ()> {
+    let (config_path, explicit) = config_path(config);
 
-        if config_metadata.is_dir() {
-            eprintln!("Config path {}\"~/.config/exconman/config.json\"{} is a directory.", util::color("green", "fg"), util::color("white", "fg"));
-            None
-        } else {
-            let config = std::fs::read_to_string(&util::expand_env_vars("~/.config/exconman/config.json"));
-
-            if config.is_ok() {
-				let config: Result<Config, serde_json::Error> = serde_json::from_str(&config.unwrap());
-
-                if config.is_err() {
-                    eprintln!(
-                        "Config Error: {}{}{}",
-                        util::color("red", "fg"),
-                        config.unwrap_err(),
-                        util::color("white", "fg"),
-                    );
-                    return None
-                }
-
-                Some(config.unwrap())
-            } else {
-                None
+    let config = match std::fs::metadata(&config_path) {
+        Err(error) => {
+            if !explicit {
+                return Ok(None);
             }
-        }
-    } else {
-        None
-    }
-}
 
-// Get the registry, whether it's a dir or file.
-pub fn get_registry(registry: Option<String>) -> Result<Vec<Setting>, ()> {
-    let registry_path: Result<String, ()> = if registry.is_some() {
-        let registry = registry.unwrap();
-        Ok(registry)
-    } else {
-        // Decide which default registry path to use, either the file or the directory
-        let registry_file = util::expand_env_vars("~/.config/exconman/registry.json");
-        let registry_dir = util::expand_env_vars("~/.config/exconman/registry");
-        
-        let file_results = std::fs::metadata(&registry_file);
-        let dir_results = std::fs::metadata(&registry_dir);
-
-        if file_results.is_err() && dir_results.is_err() {
-            eprintln!(
-                "Default registry paths \"{}{}{}\" and \"{}{}{}\" do not exist.\n\nCreate one of them or provide a custom path using {}--registry{}",
+            errln!(
+                "Failed to read config {}\"{}\"{}: {}{}{}",
                 util::color("green", "fg"),
-                registry_file,
+                config_path,
                 util::color("white", "fg"),
-                util::color("green", "fg"),
-                registry_dir,
-                util::color("white", "fg"),
-                util::color("blue", "fg"),
+                util::color("red", "fg"),
+                error,
                 util::color("white", "fg"),
             );
-            Err(())
-        } else if file_results.is_ok() {
-            Ok(registry_file)
-        } else {
-            Ok(registry_dir)
+            return Err(());
+        }
+        Ok(metadata) if metadata.is_dir() => {
+            errln!("Config path {}\"{}\"{} is a directory.", util::color("green", "fg"), config_path, util::color("white", "fg"));
+            return Err(());
+        }
+        Ok(_) => match std::fs::read_to_string(&config_path) {
+            Ok(config) => config,
+            Err(error) => {
+                errln!(
+                    "Failed to read config {}\"{}\"{}: {}{}{}",
+                    util::color("green", "fg"),
+                    config_path,
+                    util::color("white", "fg"),
+                    util::color("red", "fg"),
+                    error,
+                    util::color("white", "fg"),
+                );
+                return Err(());
+            }
         }
     };
 
-	if registry_path.is_err() {
-		return Err(());
-	}
-
-	let registry_path = registry_path.unwrap();
-
-	let registry: Result<Vec<Setting>, ()> = match std::fs::metadata(&registry_path) {
-		Err(error) => {
-            eprintln!(
-                "Failed to read {}\"{}\"{}: {}{}{}",
+    match serde_json::from_str(&config) {
+        Ok(config) => Ok(Some(config)),
+        Err(error) => {
+            errln!(
+                "Config Error in {}\"{}\"{}: {}{}{}{}",
                 util::color("green", "fg"),
-                registry_path,
+                config_path,
                 util::color("white", "fg"),
                 util::color("red", "fg"),
                 error,
-                util::color("white", "fg")
+                util::color("white", "fg"),
+                util::did_you_mean(&error.to_string()),
             );
-			return Err(());
+            Err(())
+        }
+    }
+}
+
+// The registry paths, the given ones or the ones in $EXCONMAN_REGISTRY, or the registries of the
+// default layers if there are none. Empty when none of the default layers has a registry.
+pub fn registry_paths(registries: Vec<String>) -> Vec<PathBuf> {
+    let registries: Vec<String> = match std::env::var("EXCONMAN_REGISTRY") {
+        Ok(registry) if registries.is_empty() => registry
+            .split(':')
+            .filter(|path| !path.is_empty())
+            .map(|path| path.to_string())
+            .collect(),
+        _ => registries
+    };
+
+    if registries.is_empty() {
+        return registry::default_layers()
+            .iter()
+            .filter_map(|layer| registry::find_in_layer(layer))
+            .collect();
+    }
+
+    registries
+        .iter()
+        .map(|registry| PathBuf::from(util::expand_env_vars(registry)))
+        .collect()
+}
+
+// Get the registry from the paths of `registry_paths`. Each path can be a file or a directory, and
+// settings from later paths override earlier ones by name.
+pub fn get_registry(registries: Vec<String>) -> Result<Registry, ()> {
+    let registry_paths = registry_paths(registries);
+
+    if registry_paths.is_empty() {
+        let layers: Vec<String> = registry::default_layers()
+            .iter()
+            .map(|layer| {
+                format!(
+                    "{}\"{}/registry.{{json,toml,yaml}}\"{} or {}\"{}/registry/\"{}",
+                    util::color("green", "fg"),
+                    layer.display(),
+                    util::color("white", "fg"),
+                    util::color("green", "fg"),
+                    layer.display(),
+                    util::color("white", "fg"),
+                )
+            })
+            .collect();
+
+        errln!(
+            "None of the default registry paths exist:\n\n{}\n\nCreate one of them or provide a custom path using {}--registry{}",
+            layers.join("\n"),
+            util::color("blue", "fg"),
+            util::color("white", "fg"),
+        );
+        return Err(());
+    }
+
+    let mut registry = Registry::default();
+
+    for registry_path in registry_paths {
+        let other = registry::read_path(&registry_path, &mut Vec::new())?;
+        registry::merge(&mut registry, other, false);
+    }
+
+	// Compile every regex once, reporting all the broken ones instead of stopping at the first
+	let mut failed = false;
+
+	for setting in registry.settings.iter_mut() {
+		if let Err(errors) = setting.compile() {
+			failed = true;
+
+			for error in errors {
+				errln!(
+					"Regex Error in setting {}\"{}\"{} from {}\"{}\"{}: {}{}{}",
+					util::color("green", "fg"),
+					setting.name,
+					util::color("white", "fg"),
+					util::color("green", "fg"),
+					setting.source,
+					util::color("white", "fg"),
+					util::color("red", "fg"),
+					error,
+					util::color("white", "fg"),
+				);
+			}
 		}
-		Ok(metadata) => {
-			if metadata.is_file() {
-				let registry = std::fs::read_to_string(&registry_path);
-
-				if registry.is_err() {
-                    eprintln!(
-                        "Failed to read {}\"{}\"{}",
-                        util::color("green", "fg"),
-                        registry_path,
-                        util::color("white", "fg")
-                    );
-					return Err(());
-				}
+	}
+
+	// A file is read and written once for all of its settings, so they have to agree on its encoding
+	for (i, setting) in registry.settings.iter().enumerate().filter(|(_, setting)| !setting.skipped) {
+		let path = util::expand_env_vars(&setting.file);
+		let other = registry.settings[..i]
+			.iter()
+			.filter(|other| !other.skipped && other.encoding() != setting.encoding())
+			.find(|other| util::expand_env_vars(&other.file) == path);
+
+		if let Some(other) = other {
+			failed = true;
+
+			errln!(
+				"Setting {}\"{}\"{} from {}\"{}\"{} reads {}\"{}\"{} as {}{}{} while {}\"{}\"{} from {}\"{}\"{} reads it as {}{}{}, the settings of a file need the same encoding",
+				util::color("green", "fg"),
+				setting.name,
+				util::color("white", "fg"),
+				util::color("green", "fg"),
+				setting.source,
+				util::color("white", "fg"),
+				util::color("green", "fg"),
+				setting.file,
+				util::color("white", "fg"),
+				util::color("red", "fg"),
+				setting.encoding().name(),
+				util::color("white", "fg"),
+				util::color("green", "fg"),
+				other.name,
+				util::color("white", "fg"),
+				util::color("green", "fg"),
+				other.source,
+				util::color("white", "fg"),
+				util::color("red", "fg"),
+				other.encoding().name(),
+				util::color("white", "fg"),
+			);
+		}
+	}
+
+	// Resolve dependencies, a name is looked up in the namespace of the setting first
+	let names: Vec<String> = registry.settings.iter().map(|setting| setting.name.to_string()).collect();
+
+	for setting in registry.settings.iter_mut() {
+		let namespace = setting.name.rsplit_once('.').map(|(namespace, _)| namespace.to_string());
 
-				let registry = registry.unwrap();
-				let registry: Result<Vec<Setting>, serde_json::Error> = serde_json::from_str(&registry);
+		for dependency in setting.depends_on.iter_mut().flatten() {
+			let sibling = namespace.as_ref().map(|namespace| format!("{}.{}", namespace, dependency));
 
-				if registry.is_err() {
-					eprintln!(
-						"JSON Error in {}\"{}\"{}: {}{}{}",
+			match sibling.filter(|sibling| names.contains(sibling)) {
+				Some(sibling) => *dependency = sibling,
+				None if names.contains(dependency) => {}
+				None => {
+					failed = true;
+
+					errln!(
+						"Setting {}\"{}\"{} from {}\"{}\"{} depends on {}\"{}\"{}, which doesn't exist",
+						util::color("green", "fg"),
+						setting.name,
+						util::color("white", "fg"),
 						util::color("green", "fg"),
-						registry_path,
+						setting.source,
 						util::color("white", "fg"),
-						util::color("red", "fg"),
-						registry.unwrap_err(),
+						util::color("green", "fg"),
+						dependency,
 						util::color("white", "fg"),
 					);
-					return Err(());
-				}
-				
-				let registry = registry.unwrap();
-
-				Ok(registry)
-			} else {
-                let files: Vec<Result<std::fs::DirEntry, _>> = std::fs::read_dir(registry_path)
-					.unwrap()
-					.collect();
-                let mut joined_registry: Vec<Setting> = Vec::new();
-
-				for file in files {
-                    let registry_path = file.unwrap().path();
-                    let registry_path = registry_path.display();
-                    let registry_path = registry_path.to_string();
-
-                    let registry = std::fs::read_to_string(&registry_path);
-
-					if registry.is_err() {
-                        eprintln!(
-                            "Failed to read {}\"{}\"{}: {}{}{}",
-                            util::color("green", "fg"),
-                            registry_path,
-                            util::color("white", "fg"),
-                            util::color("red", "fg"),
-                            registry.unwrap_err(),
-                            util::color("white", "fg")
-                        );
-						return Err(())
-					}
-
-					let registry = registry.unwrap();
-                    let registry: Result<Vec<Setting>, serde_json::Error> = serde_json::from_str(&registry);
-
-					if registry.is_err() {
-						eprintln!(
-							"JSON Error in {}\"{}\"{}: {}{}{}",
-							util::color("green", "fg"),
-							registry_path,
-							util::color("white", "fg"),
-							util::color("red", "fg"),
-							registry.unwrap_err(),
-							util::color("white", "fg"),
-						);
-						return Err(());
-					}
-
-					let mut registry = registry.unwrap();
-
-					joined_registry.append(&mut registry);
 				}
-				
-				Ok(joined_registry)
 			}
 		}
-	};
+	}
 
-	if registry.is_err() {
-		return Err(())
+	if failed {
+		return Err(());
 	}
 
-	Ok(registry.unwrap())
+	Ok(registry)
 }
 
 // Run a hook, does this by checking if the hook is a valid path, if it is, it runs it as a shell
@@ -192,36 +250,41 @@ pub fn get_registry(registry: Option<String>) -> Result<Vec<Setting>, ()> {
 pub fn run_hook(hook_name: String, hook_command: String) {
 
     fn run(hook_type: &str, hook_name: &str, hook_command: &str) {
-        let output: Result<std::process::Output, std::io::Error>;
-
-        if hook_type == String::from("file") {
-            output = std::process::Command::new("sh")
+        let output = if hook_type == "file" {
+            std::process::Command::new("sh")
                 .arg(hook_command)
-                .output();
+                .output()
         } else {
-            output = std::process::Command::new("sh")
+            std::process::Command::new("sh")
                 .arg("-c")
                 .arg(hook_command)
-                .output();
-        }
+                .output()
+        };
+
+        // A hook that can't be started is reported like any other failing hook, instead of
+        // taking down the daemon thread that runs it
+        let output = match output {
+            Ok(output) => output,
+            Err(_) => {
+                errln!(
+                    "Error running {}\"{}\"{} hook: {}Error occured while trying to run command \"{}\"{}",
+                    util::color("green", "fg"),
+                    hook_name,
+                    util::color("white", "fg"),
+                    util::color("red", "fg"),
+                    hook_command,
+                    util::color("white", "fg")
+                );
 
-        let stderr = String::from_utf8_lossy(&output.as_ref().unwrap().stderr);
-        let stderr = stderr.trim_end();
+                return;
+            }
+        };
 
-        if output.is_err() {
-            eprintln!(
-                "Error running {}\"{}\"{} hook: {}Error occured while trying to run command \"{}\"{}",
-                util::color("green", "fg"),
-                hook_name,
-                util::color("white", "fg"),
-                util::color("red", "fg"),
-                hook_command,
-                util::color("white", "fg")
-            );
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stderr = stderr.trim_end();
 
-            return;
-        } else if stderr.len() > 0 {
-            eprintln!(
+        if !stderr.is_empty() {
+            errln!(
                 "Error running {}\"{}\"{} hook: {}{}{}",
                 util::color("green", "fg"),
                 hook_name,
@@ -230,8 +293,6 @@ pub fn run_hook(hook_name: String, hook_command: String) {
                 stderr,
                 util::color("white", "fg")
             );
-
-            return;
         }
     }
 
@@ -249,64 +310,120 @@ pub fn run_hook(hook_name: String, hook_command: String) {
     }
 }
 
-pub fn get_setting(setting_name: String, registry: &Vec<Setting>) -> Option<&Setting> {
-    for setting in registry {
-        if setting.name == setting_name {
-            return Some(setting);
-        }
-    }
-    return None;
+pub fn get_setting(setting_name: String, registry: &[Setting]) -> Option<&Setting> {
+    registry
+        .iter()
+        .find(|setting| setting.name == setting_name)
 }
 
-pub fn set(name: String, value: String, config: &Option<Config>, registry: &Vec<Setting>) {
+// Group settings by the file they live in, keeping the order the files were first seen in
+fn group_by_file<'a, T>(items: Vec<(&'a Setting, T)>) -> Vec<(String, Vec<(&'a Setting, T)>)> {
+    let mut groups: Vec<(String, Vec<(&'a Setting, T)>)> = Vec::new();
 
-    if let Some(config) = config {
-        if let Some(hook_before_set) = &config.hook_before_set {
-            run_hook("hook_before_get".to_string(), hook_before_set.to_string());
+    for (setting, item) in items {
+        let path = util::expand_env_vars(&setting.file);
+
+        match groups.iter_mut().find(|(group_path, _)| *group_path == path) {
+            Some((_, group)) => group.push((setting, item)),
+            None => groups.push((path, vec![(setting, item)]))
         }
     }
 
-    let setting = get_setting(name, registry);
+    groups
+}
 
-    // TODO: Error message
-    if setting.is_none() {
-        return;
+// Read the file of a setting, `get_registry` makes sure all settings of one file share an encoding
+fn read_file(path: &str, setting: &Setting) -> Option<String> {
+    match encoding::read(path, setting.encoding()) {
+        Ok(file) => Some(file),
+        Err(error) => {
+            errln!(
+                "Error opening file {}\"{}\"{} for setting {}\"{}\"{}: {}{}{}",
+                util::color("green", "fg"),
+                setting.file,
+                util::color("white", "fg"),
+                util::color("green", "fg"),
+                setting.name,
+                util::color("white", "fg"),
+                util::color("red", "fg"),
+                error,
+                util::color("", "fg")
+            );
+            None
+        }
     }
+}
 
-    let setting = setting.unwrap();
+// Find the indices of the lines a line pattern targets, after applying `replace`
+fn find_lines(setting: &Setting, rgx: &Regex, file: &[String]) -> Vec<usize> {
+    let mut lines: Vec<usize> = Vec::new();
 
-    // Open the file   
-    let file = std::fs::read_to_string(&util::expand_env_vars(&setting.file));
+    for (i, line) in file.iter().enumerate() {
+        if rgx.is_match(line) {
+            match setting.replace {
+                Some(Replace::LineAbove) => {
+                    if i != 0 {
+                        lines.push(i - 1);
+                    }
+                }
+                Some(Replace::LineBelow) => {
+                    if i != file.len() - 1 {
+                        lines.push(i + 1);
+                    }
+                }
+                Some(Replace::MatchedText) | None => {
+                    lines.push(i);
+                }
+            }
 
-    if file.is_err() {
-        eprintln!(
-            "Error opening file {}\"{}\"{} for setting {}\"{}\"{}: {}{}{}",
-            util::color("green", "fg"),
-            setting.file,
-            util::color("white", "fg"),
-            util::color("green", "fg"),
-            setting.name,
-            util::color("white", "fg"),
-            util::color("red", "fg"),
-            file.unwrap_err(),
-            util::color("", "fg")
-        );
-        return;
+            if !setting.multiple() {
+                break;
+            }
+        }
     }
 
-    let file = file.unwrap();
-    // Split file into lines
-    let mut file: Vec<&str> = file
-        .split("\n")
-        .collect();
+    lines
+}
+
+// Find the lines that start and end a region, the region itself is the lines between them
+fn find_region(setting: &Setting, rgx_start: &Regex, rgx_end: &Regex, file: &[String]) -> Option<(usize, usize)> {
+    let mut region_start: Option<usize> = None;
+    let mut region_end: Option<usize> = None;
+
+    for (i, line) in file.iter().enumerate() {
+        if rgx_start.is_match(line) {
+            region_start = Some(i);
+
+            if !setting.multiple() && region_end.is_some() {
+                break;
+            }
+        }
+        if rgx_end.is_match(line) {
+            region_end = Some(i);
 
-    let substitute: String;
+            if !setting.multiple() && region_start.is_some() {
+                break;
+            }
+        }
+    }
 
-    if setting.read_value_path.is_some() && setting.read_value_path.unwrap() == true {
-        let contents = std::fs::read_to_string(&util::expand_env_vars(&value));
+    match (region_start, region_end) {
+        (Some(start), Some(end)) if start < end => Some((start, end)),
+        _ => None
+    }
+}
+
+// Build the text that replaces the matched text, reading the value from a file if the setting
+// asks for it
+fn build_substitute(setting: &Setting, value: &str) -> Option<String> {
+    if setting.read_value_path != Some(true) {
+        return Some(setting.substitute.replace("{value}", value));
+    }
 
-        if contents.is_err() {
-            eprintln!(
+    match encoding::read(&util::expand_env_vars(value), setting.encoding()) {
+        Ok(contents) => Some(setting.substitute.replace("{value}", &contents)),
+        Err(error) => {
+            errln!(
                 "Error opening file {}\"{}\"{} path provided in the value for setting {}\"{}\"{}: {}{}{}",
                 util::color("green", "fg"),
                 value,
@@ -315,127 +432,233 @@ pub fn set(name: String, value: String, config: &Option<Config>, registry: &Vec<
                 setting.name,
                 util::color("white", "fg"),
                 util::color("red", "fg"),
-                contents.unwrap_err(),
+                error,
                 util::color("", "fg")
             );
-            return;
+            None
         }
-
-        let contents = contents.unwrap();
-        substitute = setting.substitute.replace("{value}", &contents);
-    } else {
-        substitute = setting.substitute.replace("{value}", &value);
     }
+}
 
-    match &setting.pattern {
-        Pattern::Line(pattern) => {
-            let rgx = Regex::new(&pattern);
+// Substitute a value into the lines of a file that's already in memory, returns whether anything
+// actually changed
+fn apply(setting: &Setting, substitute: &str, file: &mut Vec<String>) -> bool {
+    match &setting.compiled().matcher {
+        Matcher::Line(rgx) => {
+            let mut changed = false;
+
+            for i in find_lines(setting, rgx, file) {
+                if file[i] != substitute {
+                    file[i] = substitute.to_string();
+                    changed = true;
+                }
+            }
 
-            if rgx.is_err() {
-                eprintln!(
-                    "Error occured while compiling regex for setting {}\"{}\"{}: {}{}{}",
-                    util::color("green", "fg"),
-                    setting.name,
-                    util::color("white", "fg"),
-                    util::color("red", "fg"),
-                    rgx.unwrap_err(),
-                    util::color("white", "fg"),
-                );
-                return;
+            changed
+        }
+        Matcher::Region(rgx_start, rgx_end) => {
+            let (region_start, region_end) = match find_region(setting, rgx_start, rgx_end, file) {
+                Some(region) => region,
+                None => return false
+            };
+
+            if file[region_start + 1..region_end].join("\n") == substitute {
+                return false;
             }
 
-            let rgx = rgx.unwrap();
-
-            for i in 0..file.len() {
-                let line = file[i];
-
-                if rgx.is_match(line) {
-                    match &setting.replace {
-                        None => {
-                            file[i] = &substitute;
-                        }
-                        Some(replace) => {
-                            match replace {
-                                Replace::LineAbove => {
-                                    if i != 0 {
-                                        file[i - 1] = &substitute;
-                                    }
-                                }
-                                Replace::MatchedText => {
-                                    file[i] = &substitute;
-                                }
-                                Replace::LineBelow => {
-                                    if i != file.len() - 1 {
-                                        file[i + 1] = &substitute;
-                                    }
-                                }
-                            }
-                        }
-                    }
+            file.drain(region_start + 1..region_end);
+            file.insert(region_start + 1, substitute.to_string());
+            true
+        }
+    }
+}
 
-                    if setting.multiple.is_none() || setting.multiple.unwrap() == false {
-                        break;
-                    }
-                }
+// Extract the value of a setting from the lines of a file that's already in memory
+fn extract(setting: &Setting, file: &[String]) -> Option<String> {
+    let text: String = match &setting.compiled().matcher {
+        Matcher::Line(rgx) => {
+            match find_lines(setting, rgx, file).last() {
+                Some(&i) => file[i].to_string(),
+                None => String::new()
             }
+        }
+        Matcher::Region(rgx_start, rgx_end) => {
+            let (region_start, region_end) = find_region(setting, rgx_start, rgx_end, file)?;
 
+            file[region_start + 1 .. region_end].join("\n")
         }
-        Pattern::Region(region) => {
-            let mut region_start: Option<usize> = None;
-            let mut region_end: Option<usize> = None;
-            
-            let rgx_start = Regex::new(&region[0]);
-            let rgx_end = Regex::new(&region[1]);
+    };
 
-            if rgx_start.is_err() || rgx_end.is_err() {
-                eprintln!(
-                    "Error occured while compiling regex for setting {}\"{}\"{}: {}{}{}",
-                    util::color("green", "fg"),
-                    setting.name,
-                    util::color("white", "fg"),
-                    util::color("red", "fg"),
-                    rgx_start.unwrap_err(),
-                    util::color("white", "fg"),
-                );
-                return;
+    // Now that we've extracted the text, extract the value from it.
+    match setting.compiled().value.as_ref().and_then(|value| value.captures(&text)) {
+        Some(captures) => Some(captures[1].to_string()),
+        None => {
+            errln!(
+                "Error occurred while extracting the value for setting {}\"{}\"{}: Could not find value",
+                util::color("green", "fg"),
+                setting.name,
+                util::color("white", "fg"),
+            );
+            None
+        }
+    }
+}
+
+// Order values before setting them. Settings with a lower `order` come first, a setting comes after
+// the ones it depends on, and the place in the registry decides the rest. Values of settings that
+// don't exist are reported and left out, skipped settings are left out quietly. Fails on
+// dependency cycles.
+fn plan(values: Vec<(String, String)>, registry: &[Setting]) -> Result<Vec<(&Setting, String)>, ()> {
+    let mut pending: Vec<(usize, &Setting, String)> = Vec::new();
+
+    for (name, value) in values {
+        match registry.iter().position(|setting| setting.name == name) {
+            Some(i) if registry[i].skipped => {}
+            Some(i) => pending.push((i, &registry[i], value)),
+            None => errln!(
+                "Setting {}\"{}\"{} doesn't exist, its value is left out",
+                util::color("green", "fg"),
+                name,
+                util::color("white", "fg"),
+            )
+        }
+    }
+
+    pending.sort_by_key(|(i, setting, _)| (setting.order(), *i));
+
+    let mut planned: Vec<(&Setting, String)> = Vec::new();
+
+    while !pending.is_empty() {
+        // The first setting that doesn't wait for another one
+        let ready = pending.iter().position(|(_, setting, _)| {
+            setting
+                .depends_on
+                .iter()
+                .flatten()
+                .all(|dependency| !pending.iter().any(|(_, other, _)| other.name == *dependency))
+        });
+
+        match ready {
+            Some(i) => {
+                let (_, setting, value) = pending.remove(i);
+                planned.push((setting, value));
             }
+            None => {
+                let names: Vec<String> = pending
+                    .iter()
+                    .map(|(_, setting, _)| format!("{}\"{}\"{}", util::color("green", "fg"), setting.name, util::color("white", "fg")))
+                    .collect();
+
+                errln!("The dependencies of {} form a cycle", names.join(", "));
+                return Err(());
+            }
+        }
+    }
 
-            let rgx_start = rgx_start.unwrap();
-            let rgx_end = rgx_end.unwrap();
+    Ok(planned)
+}
 
-            for i in 0..file.len() {
-                let line = file[i];
-                if rgx_start.is_match(line) {
-                    region_start = Some(i);
+// Split a plan into batches of settings of one file, a file is read and written once per batch.
+// A setting joins the last batch of its file unless a setting it depends on is in a later batch,
+// so dependencies between files are applied in order while the settings of a file stay together
+// otherwise.
+fn batch_by_file<'a>(planned: Vec<(&'a Setting, String)>) -> Vec<(String, Vec<(&'a Setting, String)>)> {
+    let mut batches: Vec<(String, Vec<(&'a Setting, String)>)> = Vec::new();
+
+    for (setting, value) in planned {
+        let path = util::expand_env_vars(&setting.file);
+
+        let depends_on = |other: &Setting| setting.depends_on.iter().flatten().any(|dependency| *dependency == other.name);
+        let earliest = batches
+            .iter()
+            .rposition(|(_, batch)| batch.iter().any(|(other, _)| depends_on(other)))
+            .map_or(0, |i| i + 1);
+
+        match batches.iter().rposition(|(batch_path, _)| *batch_path == path).filter(|&i| i >= earliest) {
+            Some(i) => batches[i].1.push((setting, value)),
+            None => batches.push((path, vec![(setting, value)]))
+        }
+    }
 
-                    if (setting.multiple.is_none() || setting.multiple.unwrap() == false) && region_end.is_some() {
-                        break;
-                    }
-                }
-                if rgx_end.is_match(line) {
-                    region_end = Some(i);
+    batches
+}
 
-                    if (setting.multiple.is_none() || setting.multiple.unwrap() == false) && region_start.is_some() {
-                        break;
-                    }
+// Set several settings at once, in the order `plan` gives them. Every file is read once and written at most once. Files that
+// wouldn't change aren't written, and the set hooks only run if something was written or `force`
+// is given. Templates referencing a changed setting are rendered before the after hook runs.
+// Returns the names of the settings whose value changed.
+pub fn set_values(values: Vec<(String, String)>, force: bool, config: &Option<Config>, registry: &Registry) -> Vec<String> {
+    match plan(values, &registry.settings) {
+        Ok(planned) => set_planned(planned, force, config, registry),
+        Err(_) => Vec::new()
+    }
+}
+
+// Set values in the order `plan` put them in, returns the names of the settings that changed
+fn set_planned(planned: Vec<(&Setting, String)>, force: bool, config: &Option<Config>, registry: &Registry) -> Vec<String> {
+    let mut changed: Vec<String> = Vec::new();
+    let mut writes: Vec<(String, String, &Setting)> = Vec::new();
+    let mut unchanged: Vec<(String, &Setting)> = Vec::new();
+
+    for (path, values) in batch_by_file(planned) {
+        // A file in several batches goes on from what the earlier batches made of it
+        let original = match writes.iter().rev().find(|(written, _, _)| *written == path) {
+            Some((_, file, _)) => file.to_string(),
+            None => match read_file(&path, values[0].0) {
+                Some(file) => file,
+                None => continue
+            }
+        };
+
+        // Split file into lines
+        let mut file: Vec<String> = original
+            .split('\n')
+            .map(|line| line.to_string())
+            .collect();
+
+        for (setting, value) in &values {
+            if let Some(substitute) = build_substitute(setting, value) {
+                if apply(setting, &substitute, &mut file) {
+                    changed.push(setting.name.to_string());
                 }
             }
+        }
 
-            if region_start.is_none() || region_end.is_none() || region_start.unwrap() >= region_end.unwrap() {
-                return;
-            }
+        let file = file.join("\n");
 
-            file.drain(region_start.unwrap() + 1..region_end.unwrap());
-            file.insert(region_start.unwrap() + 1, &substitute);
+        if file == original {
+            if !unchanged.iter().any(|(other, _)| *other == path) {
+                unchanged.push((path, values[0].0));
+            }
+            continue;
         }
+
+        writes.push((path, file, values[0].0));
+    }
+
+    // Files split into several batches are only unchanged if none of the batches changed them
+    for (_, setting) in unchanged.iter().filter(|(path, _)| !writes.iter().any(|(written, _, _)| written == path)) {
+        outln!(
+            "{}\"{}\"{} unchanged",
+            util::color("green", "fg"),
+            setting.file,
+            util::color("white", "fg"),
+        );
     }
 
+    if writes.is_empty() && !force {
+        return changed;
+    }
 
-    let file = file.join("\n");
+    if let Some(config) = config {
+        if let Some(hook_before_set) = &config.hook_before_set {
+            run_hook("hook_before_set".to_string(), hook_before_set.to_string());
+        }
+    }
 
-    match std::fs::write(util::expand_env_vars(&setting.file), file) {
-        Err(error) => {
-            eprintln!(
+    for (path, file, setting) in writes {
+        if let Err(error) = encoding::write(&path, &file, setting.encoding()) {
+            errln!(
                 "Failed to write to {}\"{}\"{}: {}{}{}",
                 util::color("green", "fg"),
                 setting.file,
@@ -445,316 +668,366 @@ pub fn set(name: String, value: String, config: &Option<Config>, registry: &Vec<
                 util::color("white", "fg"),
             );
         }
-        _ => {}
     }
 
+    template::render_changed(Some(&changed), &registry.templates, &registry.settings);
+
     if let Some(config) = config {
         if let Some(hook_after_set) = &config.hook_after_set {
-            run_hook("hook_after_get".to_string(), hook_after_set.to_string());
+            run_hook("hook_after_set".to_string(), hook_after_set.to_string());
         }
     }
+
+    changed
 }
 
-pub fn get(name: String, print: bool, config: &Option<Config>, registry: &Vec<Setting>) -> Option<String> {
-    // Don't run hooks when dumping
-    if print {
-        if let Some(config) = config {
-            if let Some(hook_after_get) = &config.hook_after_get {
-                run_hook("hook_after_get".to_string(), hook_after_get.to_string());
+// Get several settings at once, every file is read once. Settings whose value can't be found
+// are left out.
+pub fn get_values(names: Vec<String>, registry: &[Setting]) -> Vec<(String, String)> {
+    let settings: Vec<(&Setting, ())> = names
+        .into_iter()
+        .filter_map(|name| get_setting(name, registry).map(|setting| (setting, ())))
+        .filter(|(setting, _)| !setting.skipped)
+        .collect();
+
+    let mut values: Vec<(String, String)> = Vec::new();
+
+    for (path, settings) in group_by_file(settings) {
+        let file = match read_file(&path, settings[0].0) {
+            Some(file) => file,
+            None => continue
+        };
+
+        // Split file into lines
+        let file: Vec<String> = file
+            .split('\n')
+            .map(|line| line.to_string())
+            .collect();
+
+        for (setting, _) in settings {
+            if let Some(value) = extract(setting, &file) {
+                values.push((setting.name.to_string(), value));
             }
         }
     }
 
-    let setting = get_setting(name, registry);
+    values
+}
 
-    // TODO: Error message
-    if setting.is_none() {
-        return None;
+// Tell the user a setting they asked for by name is skipped on this machine
+pub fn report_skipped(name: &str, registry: &[Setting]) -> bool {
+    match get_setting(name.to_string(), registry) {
+        Some(setting) if setting.skipped => {
+            errln!(
+                "Setting {}\"{}\"{} is skipped, its {}when{} conditions don't hold on this machine",
+                util::color("green", "fg"),
+                setting.name,
+                util::color("white", "fg"),
+                util::color("blue", "fg"),
+                util::color("white", "fg"),
+            );
+            true
+        }
+        _ => false
     }
+}
 
-    let setting = setting.unwrap();
+pub fn set(name: String, value: String, force: bool, config: &Option<Config>, registry: &Registry) {
+    if report_skipped(&name, &registry.settings) {
+        return;
+    }
 
-    // Open the file   
-    let file = std::fs::read_to_string(&util::expand_env_vars(&setting.file));
+    set_values(vec![(name, value)], force, config, registry);
+}
 
-    if file.is_err() {
-        eprintln!(
-            "Error opening file {}\"{}\"{} for setting {}\"{}\"{}: {}{}{}",
-            util::color("green", "fg"),
-            setting.file,
-            util::color("white", "fg"),
-            util::color("green", "fg"),
-            setting.name,
-            util::color("white", "fg"),
-            util::color("red", "fg"),
-            file.unwrap_err(),
-            util::color("", "fg")
-        );
-        return None;
+// Get values with the get hooks run around reading them
+pub fn get_with_hooks(names: Vec<String>, config: &Option<Config>, registry: &[Setting]) -> Vec<(String, String)> {
+    if let Some(config) = config {
+        if let Some(hook_before_get) = &config.hook_before_get {
+            run_hook("hook_before_get".to_string(), hook_before_get.to_string());
+        }
     }
 
-    let file = file.unwrap();
-    // Split file into lines
-    let file: Vec<&str> = file
-        .split("\n")
-        .collect();
+    let values = get_values(names, registry);
 
-    let mut text: String = String::new();
+    if let Some(config) = config {
+        if let Some(hook_after_get) = &config.hook_after_get {
+            run_hook("hook_after_get".to_string(), hook_after_get.to_string());
+        }
+    }
 
-    match &setting.pattern {
-        Pattern::Line(pattern) => {
-            let rgx = Regex::new(&pattern);
+    values
+}
 
-            if rgx.is_err() {
-                eprintln!(
-                    "Error occured while compiling regex for setting {}\"{}\"{}: {}{}{}",
-                    util::color("green", "fg"),
-                    setting.name,
-                    util::color("white", "fg"),
-                    util::color("red", "fg"),
-                    rgx.unwrap_err(),
-                    util::color("white", "fg"),
-                );
-                return None;
-            }
+pub fn print_get(values: &[(String, String)], format: Option<Format>) {
+    if let Some(format) = format {
+        outln!("{}", format::render(values, format));
+    } else if let Some((_, value)) = values.first() {
+        outln!("{}", value);
+    }
+}
 
-            let rgx = rgx.unwrap();
-
-            for i in 0..file.len() {
-                let line = file[i];
-
-                if rgx.is_match(line) {
-                    match &setting.replace {
-                        None => {
-                            text = file[i].to_string();
-                        }
-                        Some(replace) => {
-                            match replace {
-                                Replace::LineAbove => {
-                                    if i != 0 {
-                                        text = file[i - 1].to_string();
-                                    }
-                                }
-                                Replace::MatchedText => {
-                                    text = file[i].to_string();
-                                }
-                                Replace::LineBelow => {
-                                    if i != file.len() - 1 {
-                                        text = file[i + 1].to_string();
-                                    }
-                                }
-                            }
-                        }
-                    }
+pub fn get(name: String, format: Option<Format>, config: &Option<Config>, registry: &[Setting]) {
+    if report_skipped(&name, registry) {
+        return;
+    }
 
-                    if setting.multiple.is_none() || setting.multiple.unwrap() == false {
-                        break;
-                    }
-                }
-            }
+    print_get(&get_with_hooks(vec![name], config, registry), format);
+}
 
-        }
-        Pattern::Region(region) => {
-            let mut region_start: Option<usize> = None;
-            let mut region_end: Option<usize> = None;
-            
-            let rgx_start = Regex::new(&region[0]);
-            let rgx_end = Regex::new(&region[1]);
+// Apply one or more profiles, later ones override the values of earlier ones. `dry_run` only
+// prints the order the values would be set in.
+pub fn load(profiles: Vec<String>, force: bool, dry_run: bool, config: &Option<Config>, registry: &Registry) {
+    let profile = match profile::read(&profiles) {
+        Ok(profile) => profile,
+        Err(_) => return
+    };
 
-            if rgx_start.is_err() || rgx_end.is_err() {
-                eprintln!(
-                    "Error occured while compiling regex for setting {}\"{}\"{}: {}{}{}",
-                    util::color("green", "fg"),
-                    setting.name,
-                    util::color("white", "fg"),
-                    util::color("red", "fg"),
-                    rgx_start.unwrap_err(),
-                    util::color("white", "fg"),
-                );
-                return None;
-            }
+    // A profile that can't be applied isn't recorded as the current one
+    let planned = match plan(profile.into_iter().collect(), &registry.settings) {
+        Ok(planned) => planned,
+        Err(_) => return
+    };
 
-            let rgx_start = rgx_start.unwrap();
-            let rgx_end = rgx_end.unwrap();
+    if dry_run {
+        // In the order they're applied in, which keeps the settings of a file together
+        let planned: Vec<(&Setting, String)> = batch_by_file(planned)
+            .into_iter()
+            .flat_map(|(_, batch)| batch)
+            .collect();
+
+        for (i, (setting, value)) in planned.iter().enumerate() {
+            outln!(
+                "{}. {}\"{}\"{} = \"{}\" in {}\"{}\"{}",
+                i + 1,
+                util::color("green", "fg"),
+                setting.name,
+                util::color("white", "fg"),
+                value,
+                util::color("green", "fg"),
+                setting.file,
+                util::color("white", "fg"),
+            );
+        }
 
-            for i in 0..file.len() {
-                let line = file[i];
-                if rgx_start.is_match(line) {
-                    region_start = Some(i);
+        return;
+    }
 
-                    if (setting.multiple.is_none() || setting.multiple.unwrap() == false) && region_end.is_some() {
-                        break;
-                    }
-                }
-                if rgx_end.is_match(line) {
-                    region_end = Some(i);
+    set_planned(planned, force, config, registry);
+    profile::set_current(&profiles);
+}
 
-                    if (setting.multiple.is_none() || setting.multiple.unwrap() == false) && region_start.is_some() {
-                        break;
-                    }
-                }
-            }
+// Load the next, previous or a random profile of the profiles directory
+pub fn cycle(action: &str, tag: Option<String>, glob: Option<String>, seed: Option<u64>, force: bool, config: &Option<Config>, registry: &Registry) {
+    let candidates = profile::filter(tag.as_deref(), glob.as_deref());
 
-            if region_start.is_none() || region_end.is_none() || region_start.unwrap() >= region_end.unwrap() {
-                return None;
-            }
+    match profile::pick(&candidates, action, seed) {
+        Some(name) => {
+            outln!("Loading {}\"{}\"{}", util::color("green", "fg"), name, util::color("white", "fg"));
 
-            text = file[region_start.unwrap() + 1 .. region_end.unwrap()]
-                .join("\n");
-        }
-
-    }
-
-    // Now that we've extracted the text, extact the value from it.
-    let built_rgx = &setting.substitute;
-    let built_rgx = built_rgx.replace("\\", "\\\\");
-    let built_rgx = built_rgx.replace("^", "\\^");
-    let built_rgx = built_rgx.replace("$", "\\$");
-    let built_rgx = built_rgx.replace("|", "\\|");
-    let built_rgx = built_rgx.replace("?", "\\?");
-    let built_rgx = built_rgx.replace(".", "\\.");
-    let built_rgx = built_rgx.replace("*", "\\*");
-    let built_rgx = built_rgx.replace("(", "\\(");
-    let built_rgx = built_rgx.replace(")", "\\)");
-    let built_rgx = built_rgx.replace("{value}", "(.|\n)*"); // Match all characters, even new lines
-    let built_rgx = built_rgx.replace("+", "\\+");
-    let built_rgx = built_rgx.replace("{", "\\{");
-    let built_rgx = built_rgx.replace("[", "\\[");
-    let built_rgx = Regex::new(&built_rgx);
-    if built_rgx.is_err() {
-        eprintln!(
-            "Error occured while compiling auto generated regex for setting {}\"{}\"{}: {}{}{}",
+            // By path, a file of the same name in the current directory would be found first
+            let path = profile::profiles_dir().join(format!("{}.json", name));
+            load(vec![path.display().to_string()], force, false, config, registry);
+        }
+        None => errln!(
+            "No profiles to pick from in {}\"{}\"{}",
             util::color("green", "fg"),
-            setting.name,
-            util::color("white", "fg"),
-            util::color("red", "fg"),
-            built_rgx.unwrap_err(),
+            profile::profiles_dir().display(),
             util::color("white", "fg"),
-        );
-        return None;
+        )
     }
-    let built_rgx = built_rgx.unwrap();
+}
 
-    let start_of_text = built_rgx.find(&text);
+// Show what loading `to` over `from` changes. Without `from`, the profile is compared against the
+// live values of the settings it holds.
+pub fn diff(from: String, to: Option<String>, registry: Option<&Registry>) {
+    let (from, to) = match (to, registry) {
+        (Some(to), _) => match (profile::read(&[from]), profile::read(&[to])) {
+            (Ok(from), Ok(to)) => (from, to),
+            _ => return
+        },
+        (None, Some(registry)) => {
+            let to = match profile::read(&[from]) {
+                Ok(to) => to,
+                Err(_) => return
+            };
 
-    if start_of_text.is_none() {
-        eprintln!(
-            "Error occurred while extracting the value for setting {}\"{}\"{}: Could not find value",
-            util::color("green", "fg"),
-            setting.name,
-            util::color("white", "fg"),
-        );
-        return None;
-    }
+            let live: Profile = get_values(to.keys().cloned().collect(), &registry.settings)
+                .into_iter()
+                .collect();
 
-    let start_of_text = start_of_text
-        .unwrap()
-        .start();
+            (live, to)
+        }
+        (None, None) => return
+    };
 
-    let start_of_value = setting.substitute.find("{value}");
-    if start_of_value.is_none() {
-        eprintln!(
-            "Error occurred while extracting the value for setting {}\"{}\"{}: Could not find value",
-            util::color("green", "fg"),
-            setting.name,
-            util::color("white", "fg"),
-        );
-        return None;
+    if !profile::diff(&from, &to) {
+        outln!("No differences");
     }
-    let start_of_value = start_of_value.unwrap();
+}
 
-    let mut text: Vec<&str> = text
-        .split("")
+// The values of every setting of the registry
+pub fn dump_values(registry: &[Setting]) -> Vec<(String, String)> {
+    let names: Vec<String> = registry
+        .iter()
+        .map(|setting| setting.name.to_string())
         .collect();
 
-    // Remove chars before the value
-    for i in 0..start_of_text + start_of_value + 1 {
-        text[i] = "";
+    get_values(names, registry)
+}
+
+pub fn print_dump(mut values: Vec<(String, String)>, format: Option<Format>) {
+    if let Some(format) = format {
+        values.sort();
+        outln!("{}", format::render(&values, format));
+        return;
     }
 
-    let amount_of_chars_after_value = setting.substitute[start_of_value + "{value}".len()..].len() + 1;
+    let settings: std::collections::BTreeMap<String, String> = values
+        .into_iter()
+        .collect();
+    
+    let json = serde_json::to_string_pretty(&settings);
 
-    // Remove chars after the value
-    for i in 0..amount_of_chars_after_value {
-        let end_of_text = text.len() - 1;
-        text[end_of_text - i] = "";
+    if let Ok(json) = json {
+        outln!("{}", json);
+    } else {
+        errln!("Failed to generate JSON");
     }
+}
 
-    let text = text.join("");
+pub fn dump(format: Option<Format>, registry: &[Setting]) {
+    print_dump(dump_values(registry), format);
+}
 
-    if print {
-        println!("{}", text);
-    } else {
-        return Some(text);
+pub fn list(format: Option<Format>, registry: &[Setting]) {
+    if let Some(format) = format {
+        let settings: Vec<(String, String)> = registry
+            .iter()
+            .map(|setting| (setting.name.to_string(), setting.file.to_string()))
+            .collect();
+
+        outln!("{}", format::render(&settings, format));
+        return;
     }
 
-    if print {
-        if let Some(config) = config {
-            if let Some(hook_after_get) = &config.hook_after_get {
-                run_hook("hook_after_get".to_string(), hook_after_get.to_string());
-            }
-        }
+    for setting in registry {
+        outln!("{}", setting.name);
     }
+}
 
-    return None
+// Render every template of the registry, for example after editing one
+pub fn render(registry: &Registry) {
+    template::render_changed(None, &registry.templates, &registry.settings);
 }
 
-pub fn load(file: String, config: &Option<Config>, registry: &Vec<Setting>) {
-    let settings = std::fs::read_to_string(&file);
+// Print the JSON Schema of registry or config files
+pub fn schema(kind: &str) {
+    let schema = match kind {
+        "config" => schemars::schema_for!(Config),
+        _ => registry::schema()
+    };
 
-    if settings.is_err() {
-        eprintln!(
-            "Failed to read file {}\"{}\"{}: {}{}{}",
-            util::color("green", "fg"),
-            file,
-            util::color("white", "fg"),
-            util::color("red", "fg"),
-            settings.unwrap_err(),
-            util::color("white", "fg"),
-        );
-        return;
+    match serde_json::to_string_pretty(&schema) {
+        Ok(json) => outln!("{}", json),
+        Err(_) => errln!("Failed to generate JSON")
     }
+}
 
-    let settings = settings.unwrap();
-    let settings: Result<HashMap<String, String>, serde_json::Error> = serde_json::from_str(&settings);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setting(name: &str, file: &str, order: Option<i64>, depends_on: &[&str]) -> Setting {
+        serde_json::from_value(serde_json::json!({
+            "name": name,
+            "file": file,
+            "pattern": "^x",
+            "substitute": "{value}",
+            "order": order,
+            "depends_on": if depends_on.is_empty() { None } else { Some(depends_on) }
+        }))
+        .unwrap()
+    }
 
-    if settings.is_err() {
-        eprintln!(
-            "JSON error in file {}\"{}\"{}: {}{}{}",
-            util::color("green", "fg"),
-            file,
-            util::color("white", "fg"),
-            util::color("red", "fg"),
-            settings.unwrap_err(),
-            util::color("white", "fg"),
-        );
-        return;
+    fn values(names: &[&str]) -> Vec<(String, String)> {
+        names.iter().map(|name| (name.to_string(), String::new())).collect()
     }
 
-    let settings = settings.unwrap();
+    fn names(planned: &[(&Setting, String)]) -> Vec<String> {
+        planned.iter().map(|(setting, _)| setting.name.to_string()).collect()
+    }
 
-    for (name, value) in settings {
-        set(name, value, config, registry);
+    #[test]
+    fn plan_orders_by_order_then_registry() {
+        let registry = vec![
+            setting("a", "one", None, &[]),
+            setting("b", "one", Some(-1), &[]),
+            setting("c", "one", Some(1), &[]),
+            setting("d", "one", None, &[])
+        ];
+
+        let planned = plan(values(&["c", "d", "a", "b"]), &registry).unwrap();
+        assert_eq!(names(&planned), ["b", "a", "d", "c"]);
     }
-}
 
-pub fn dump(config: &Option<Config>, registry: &Vec<Setting>) {
-    let mut settings: std::collections::BTreeMap<String, String> = std::collections::BTreeMap::new();
+    #[test]
+    fn plan_puts_dependencies_first() {
+        let registry = vec![
+            setting("a", "one", None, &["c"]),
+            setting("b", "one", Some(-1), &["a"]),
+            setting("c", "one", Some(1), &[])
+        ];
 
-    for setting in registry {
-        let name = &setting.name;
-        let value = get(name.to_string(), false, config, registry);
+        let planned = plan(values(&["a", "b", "c"]), &registry).unwrap();
+        assert_eq!(names(&planned), ["c", "a", "b"]);
 
-        if let Some(value) = value {
-            settings.insert(name.to_string(), value);
-        }
+        // Dependencies that aren't set together don't hold anything up
+        let planned = plan(values(&["a", "b"]), &registry).unwrap();
+        assert_eq!(names(&planned), ["a", "b"]);
     }
-    
-    let json = serde_json::to_string_pretty(&settings);
 
-    if let Ok(json) = json {
-        println!("{}", json);
-    } else {
-        eprintln!("Failed to generate JSON");
+    #[test]
+    fn plan_leaves_out_unknown_settings() {
+        let registry = vec![setting("a", "one", None, &[]), setting("b", "one", None, &[])];
+
+        let planned = plan(values(&["b", "typo", "a"]), &registry).unwrap();
+        assert_eq!(names(&planned), ["a", "b"]);
+    }
+
+    #[test]
+    fn plan_fails_on_cycles() {
+        let registry = vec![
+            setting("a", "one", None, &["c"]),
+            setting("b", "one", None, &["a"]),
+            setting("c", "one", None, &["b"]),
+            setting("d", "one", None, &[])
+        ];
+
+        assert!(plan(values(&["a", "b", "c", "d"]), &registry).is_err());
+        assert!(plan(values(&["a", "b", "d"]), &registry).is_ok());
+    }
+
+    #[test]
+    fn batches_split_on_dependencies_across_files() {
+        let registry = vec![
+            setting("a", "one", None, &[]),
+            setting("b", "two", None, &["a"]),
+            setting("c", "one", None, &["b"]),
+            setting("d", "one", None, &[]),
+            setting("e", "two", None, &[])
+        ];
+
+        let planned = plan(values(&["a", "b", "c", "d", "e"]), &registry).unwrap();
+        let batches: Vec<(String, Vec<String>)> = batch_by_file(planned)
+            .into_iter()
+            .map(|(path, batch)| (path, names(&batch)))
+            .collect();
+
+        assert_eq!(batches, [
+            ("one".to_string(), vec!["a".to_string()]),
+            ("two".to_string(), vec!["b".to_string(), "e".to_string()]),
+            ("one".to_string(), vec!["c".to_string(), "d".to_string()])
+        ]);
     }
 }
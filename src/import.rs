@@ -0,0 +1,271 @@
+//! Handles turning color palettes from other tools into profiles
+
+use std::collections::BTreeMap;
+
+use regex::Regex;
+
+use crate::util;
+
+pub const SOURCES: &[&str] = &["pywal", "base16", "xresources"];
+
+type Palette = BTreeMap<String, String>;
+
+// Give bare hex colors such as "282828" the "#" every other source uses
+fn normalize_color(value: &str) -> String {
+    let value = value.trim().trim_matches('"');
+
+    if value.len() == 6 && value.chars().all(|c| c.is_ascii_hexdigit()) {
+        format!("#{}", value)
+    } else {
+        value.to_string()
+    }
+}
+
+// pywal's colors.json, "special" and "colors" are flattened into one palette
+fn read_pywal(contents: &str) -> Result<Palette, String> {
+    let json: serde_json::Value = serde_json::from_str(contents).map_err(|error| error.to_string())?;
+    let mut palette = Palette::new();
+
+    for group in &["special", "colors"] {
+        if let Some(colors) = json.get(group).and_then(|colors| colors.as_object()) {
+            for (name, value) in colors {
+                if let Some(value) = value.as_str() {
+                    palette.insert(name.to_string(), value.to_string());
+                }
+            }
+        }
+    }
+
+    for name in &["wallpaper", "alpha"] {
+        if let Some(value) = json.get(name).and_then(|value| value.as_str()) {
+            palette.insert(name.to_string(), value.to_string());
+        }
+    }
+
+    Ok(palette)
+}
+
+// base16 and base24 schemes, both the classic flat ones and the newer ones with a "palette" table
+fn read_base16(contents: &str) -> Result<Palette, String> {
+    let yaml: BTreeMap<String, serde_yaml::Value> = serde_yaml::from_str(contents).map_err(|error| error.to_string())?;
+    let mut palette = Palette::new();
+
+    let mut add = |name: &str, value: &serde_yaml::Value| {
+        if let Some(value) = value.as_str() {
+            if name.starts_with("base") {
+                palette.insert(name.to_string(), normalize_color(value));
+            } else {
+                palette.insert(name.to_string(), value.to_string());
+            }
+        }
+    };
+
+    for (name, value) in &yaml {
+        match value {
+            serde_yaml::Value::Mapping(colors) if name == "palette" => {
+                for (name, value) in colors {
+                    if let Some(name) = name.as_str() {
+                        add(name, value);
+                    }
+                }
+            }
+            value => add(name, value)
+        }
+    }
+
+    Ok(palette)
+}
+
+// "*.color0: #282828" lines of an .Xresources file, keyed by the last part of the resource name.
+// Colors given through "#define" are resolved.
+fn read_xresources(contents: &str) -> Result<Palette, String> {
+    let define = Regex::new(r"^#define\s+(\S+)\s+(.+)$").unwrap();
+    let resource = Regex::new(r"^[^!#:]*?[*.]?([A-Za-z0-9_]+)\s*:\s*(.+)$").unwrap();
+
+    let mut defines: BTreeMap<String, String> = BTreeMap::new();
+    let mut palette = Palette::new();
+
+    for line in contents.lines() {
+        let line = line.trim();
+
+        if let Some(captures) = define.captures(line) {
+            defines.insert(captures[1].to_string(), captures[2].trim().to_string());
+        } else if line.starts_with('!') || line.starts_with('#') {
+            continue;
+        } else if let Some(captures) = resource.captures(line) {
+            let value = captures[2].trim();
+            let value = defines.get(value).map(|value| value.as_str()).unwrap_or(value);
+
+            palette.insert(captures[1].to_string(), value.to_string());
+        }
+    }
+
+    Ok(palette)
+}
+
+// Fill in a mapping entry, either the name of a palette key or a text with "{key}" placeholders
+fn resolve(entry: &str, palette: &Palette) -> Result<String, String> {
+    if !entry.contains('{') {
+        return palette
+            .get(entry)
+            .cloned()
+            .ok_or_else(|| format!("The palette has no \"{}\"", entry));
+    }
+
+    let placeholder = Regex::new(r"\{([^{}]+)\}").unwrap();
+    let mut missing: Option<String> = None;
+
+    let value = placeholder.replace_all(entry, |captures: &regex::Captures| {
+        match palette.get(&captures[1]) {
+            Some(value) => value.to_string(),
+            None => {
+                missing = Some(captures[1].to_string());
+                String::new()
+            }
+        }
+    });
+
+    match missing {
+        Some(key) => Err(format!("The palette has no \"{}\"", key)),
+        None => Ok(value.to_string())
+    }
+}
+
+fn report(path: &str, error: impl std::fmt::Display) {
+    eprintln!(
+        "Failed to import {}\"{}\"{}: {}{}{}",
+        util::color("green", "fg"),
+        path,
+        util::color("white", "fg"),
+        util::color("red", "fg"),
+        error,
+        util::color("white", "fg"),
+    );
+}
+
+// Read a palette, map it onto setting names with the mapping file and print or write the profile
+pub fn import(source: &str, file: Option<String>, mapping: String, output: Option<String>) {
+    let file = match (source, file) {
+        (_, Some(file)) => file,
+        ("pywal", None) => "~/.cache/wal/colors.json".to_string(),
+        (_, None) => {
+            eprintln!(
+                "The {}\"{}\"{} source needs a file to import",
+                util::color("green", "fg"),
+                source,
+                util::color("white", "fg"),
+            );
+            return;
+        }
+    };
+
+    let contents = match std::fs::read_to_string(util::expand_env_vars(&file)) {
+        Ok(contents) => contents,
+        Err(error) => return report(&file, error)
+    };
+
+    let palette = match source {
+        "pywal" => read_pywal(&contents),
+        "base16" => read_base16(&contents),
+        _ => read_xresources(&contents)
+    };
+
+    let palette = match palette {
+        Ok(palette) => palette,
+        Err(error) => return report(&file, error)
+    };
+
+    let mapping: BTreeMap<String, String> = match std::fs::read_to_string(util::expand_env_vars(&mapping)) {
+        Ok(contents) => match serde_json::from_str(&contents) {
+            Ok(mapping) => mapping,
+            Err(error) => return report(&mapping, error)
+        },
+        Err(error) => return report(&mapping, error)
+    };
+
+    let mut profile: BTreeMap<String, String> = BTreeMap::new();
+
+    for (name, entry) in &mapping {
+        match resolve(entry, &palette) {
+            Ok(value) => {
+                profile.insert(name.to_string(), value);
+            }
+            Err(error) => {
+                let keys: Vec<&str> = palette.keys().map(|key| key.as_str()).collect();
+
+                return report(&file, format!("{} for setting \"{}\", it has: {}", error, name, keys.join(", ")));
+            }
+        }
+    }
+
+    let json = match serde_json::to_string_pretty(&profile) {
+        Ok(json) => json,
+        Err(_) => {
+            eprintln!("Failed to generate JSON");
+            return;
+        }
+    };
+
+    match output {
+        Some(output) => {
+            if let Err(error) = std::fs::write(util::expand_env_vars(&output), json + "\n") {
+                eprintln!(
+                    "Failed to write to {}\"{}\"{}: {}{}{}",
+                    util::color("green", "fg"),
+                    output,
+                    util::color("white", "fg"),
+                    util::color("red", "fg"),
+                    error,
+                    util::color("white", "fg"),
+                );
+            }
+        }
+        None => println!("{}", json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_xresources_resolves_defines() {
+        let contents = "! comment\n#define bg #282828\n*.background: bg\nURxvt*color1: #cc241d\n*.foreground:#ebdbb2\n";
+        let palette = read_xresources(contents).unwrap();
+
+        assert_eq!(palette["background"], "#282828");
+        assert_eq!(palette["color1"], "#cc241d");
+        assert_eq!(palette["foreground"], "#ebdbb2");
+        assert_eq!(palette.len(), 3);
+    }
+
+    #[test]
+    fn read_base16_reads_flat_and_palette_schemes() {
+        let flat = "scheme: \"Gruvbox\"\nbase00: \"282828\"\nbase08: \"#fb4934\"\n";
+        let palette = read_base16(flat).unwrap();
+
+        assert_eq!(palette["scheme"], "Gruvbox");
+        assert_eq!(palette["base00"], "#282828");
+        assert_eq!(palette["base08"], "#fb4934");
+
+        let nested = "name: Gruvbox\npalette:\n  base00: \"282828\"\n  base0F: \"d65d0e\"\n";
+        let palette = read_base16(nested).unwrap();
+
+        assert_eq!(palette["name"], "Gruvbox");
+        assert_eq!(palette["base00"], "#282828");
+        assert_eq!(palette["base0F"], "#d65d0e");
+    }
+
+    #[test]
+    fn resolve_fills_in_keys_and_placeholders() {
+        let palette: Palette = vec![("color0", "#000000"), ("color7", "#ffffff")]
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+
+        assert_eq!(resolve("color0", &palette).unwrap(), "#000000");
+        assert_eq!(resolve("{color0}:{color7}", &palette).unwrap(), "#000000:#ffffff");
+        assert_eq!(resolve("color1", &palette).unwrap_err(), "The palette has no \"color1\"");
+        assert_eq!(resolve("{color0} {color9}", &palette).unwrap_err(), "The palette has no \"color9\"");
+    }
+}
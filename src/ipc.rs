@@ -0,0 +1,448 @@
+//! Handles the daemon socket, so bars and scripts can get and set values without re-reading the
+//! config and the registry every time
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
+
+use serde_derive::{Serialize, Deserialize};
+
+use crate::util;
+use crate::profile;
+use crate::schedule;
+use crate::functions;
+use crate::config::Config;
+use crate::registry::Registry;
+use crate::args::{SubCommands, Get, Set, Load, Dump};
+
+// Set in the daemon so hooks calling exconman don't wait on the daemon that's running them
+const NO_DAEMON: &str = "EXCONMAN_NO_DAEMON";
+
+// One request per line, answered by one response per line
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub enum Request {
+    Get { names: Vec<String> },
+    Set { values: Vec<(String, String)>, #[serde(default)] force: bool },
+    Load { profiles: Vec<String>, #[serde(default)] force: bool },
+    Dump
+}
+
+// The config, registry and state a command would use, the daemon only answers for its own
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Paths {
+    pub config: String,
+    pub registries: Vec<String>,
+    pub state: String
+}
+
+impl Paths {
+    // Resolved like `get_config` and `get_registry` do, relative paths are made absolute since the
+    // daemon runs in another directory
+    pub fn resolve(config: Option<String>, registries: Vec<String>) -> Paths {
+        let absolute = |path: &Path| std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()).display().to_string();
+
+        Paths {
+            config: absolute(Path::new(&functions::config_path(config).0)),
+            registries: functions::registry_paths(registries).iter().map(|path| absolute(path)).collect(),
+            state: util::state_dir()
+        }
+    }
+}
+
+// A request with the paths of the command sending it. Scripts writing to the socket themselves can
+// leave the paths out.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Message {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub paths: Option<Paths>,
+    #[serde(flatten)]
+    pub request: Request
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Response {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub values: Option<Vec<(String, String)>>,
+    // What running the command printed, the command that sent the request prints it again
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub output: Vec<util::Output>,
+    // The request couldn't be read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    // The paths of the request aren't the daemon's, so nothing was done and the command runs itself
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub declined: bool
+}
+
+fn uid() -> u32 {
+    std::fs::metadata("/proc/self").map(|metadata| metadata.uid()).unwrap_or_default()
+}
+
+// "$XDG_RUNTIME_DIR/exconman.sock", or a socket per user in /tmp
+pub fn socket_path() -> PathBuf {
+    match std::env::var("XDG_RUNTIME_DIR") {
+        Ok(runtime_dir) if runtime_dir.starts_with('/') => PathBuf::from(runtime_dir).join("exconman.sock"),
+        _ => PathBuf::from(format!("/tmp/exconman-{}.sock", uid()))
+    }
+}
+
+// Whether the path is a socket of this user, anyone can create one at the path in /tmp
+fn owned(path: &Path) -> bool {
+    std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket() && metadata.uid() == uid())
+}
+
+// The config and registry the daemon keeps in memory, read again when one of their files changes
+// or a `when` turns out differently
+struct State {
+    config_path: Option<String>,
+    registries: Vec<String>,
+    config: Option<Config>,
+    registry: Registry,
+    stamps: Vec<(PathBuf, Option<SystemTime>)>
+}
+
+fn lock(state: &Mutex<State>) -> MutexGuard<'_, State> {
+    state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+// The config, the registry files and their directories, so added and removed files are noticed as
+// well
+fn stamps(config_path: &str, registry: &Registry) -> Vec<(PathBuf, Option<SystemTime>)> {
+    let mut paths: Vec<PathBuf> = registry.settings
+        .iter()
+        .map(|setting| setting.source.to_string())
+        .chain(registry.templates.iter().map(|template| template.origin.to_string()))
+        .chain(std::iter::once(config_path.to_string()))
+        .map(PathBuf::from)
+        .flat_map(|path| {
+            let dir = path.parent().map(|dir| dir.to_path_buf());
+            std::iter::once(path).chain(dir)
+        })
+        .collect();
+
+    paths.sort();
+    paths.dedup();
+
+    paths
+        .into_iter()
+        .map(|path| {
+            let modified = modified(&path);
+            (path, modified)
+        })
+        .collect()
+}
+
+impl State {
+    // Read the config and the registry again if they changed. Fails if either of them is broken
+    // now, nothing is kept from them and the next call reads them again.
+    fn refresh(&mut self) -> Result<(), ()> {
+        if self.stamps.iter().all(|(path, stamp)| modified(path) == *stamp) && !self.registry.conditions_changed() {
+            return Ok(());
+        }
+
+        let config = functions::get_config(self.config_path.clone())?;
+        let registry = functions::get_registry(self.registries.clone())?;
+
+        self.stamps = stamps(&functions::config_path(self.config_path.clone()).0, &registry);
+        self.config = config;
+        self.registry = registry;
+
+        Ok(())
+    }
+}
+
+// Run a request like the command would, what it prints is sent back along with the values
+fn handle(message: Message, state: &Mutex<State>) -> Response {
+    let mut state = lock(state);
+
+    if let Some(paths) = &message.paths {
+        if *paths != Paths::resolve(state.config_path.clone(), state.registries.clone()) {
+            return Response { declined: true, ..Response::default() };
+        }
+    }
+
+    let (values, output) = util::capture(|| {
+        // The errors of a broken config or registry are sent instead of answering from the old one
+        state.refresh().ok()?;
+
+        let config = &state.config;
+        let registry = &state.registry;
+
+        match message.request {
+            Request::Get { names } => {
+                if names.iter().any(|name| functions::report_skipped(name, &registry.settings)) {
+                    return None;
+                }
+
+                Some(functions::get_with_hooks(names, config, &registry.settings))
+            }
+            Request::Set { values, force } => {
+                if !values.iter().any(|(name, _)| functions::report_skipped(name, &registry.settings)) {
+                    functions::set_values(values, force, config, registry);
+                }
+
+                None
+            }
+            Request::Load { profiles, force } => {
+                functions::load(profiles, force, false, config, registry);
+                None
+            }
+            Request::Dump => Some(functions::dump_values(&registry.settings))
+        }
+    });
+
+    Response { values, output, ..Response::default() }
+}
+
+fn serve_client(stream: UnixStream, state: &Mutex<State>) {
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(_) => return
+    };
+
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => return
+        };
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match serde_json::from_str(&line) {
+            Ok(message) => handle(message, state),
+            Err(error) => Response { error: Some(error.to_string()), ..Response::default() }
+        };
+
+        let json = serde_json::to_string(&response).unwrap_or_default();
+
+        if writeln!(writer, "{}", json).is_err() {
+            return;
+        }
+    }
+}
+
+// Run the daemon, it serves the socket and follows the schedule of the config if there is one.
+// With `once` only the schedule is applied a single time. `config_path` and `registries` are the
+// ones given on the command line, to read the config and the registry again when they change.
+pub fn daemon(once: bool, force: bool, config_path: Option<String>, registries: Vec<String>, config: Option<Config>, registry: Registry) {
+    if once {
+        match &config {
+            Some(_) if schedule::has_rules(&config) => {
+                schedule::run(true, || config.clone(), |profiles| functions::load(profiles, force, false, &config, &registry));
+            }
+            _ => eprintln!(
+                "There's no {}schedule{} in the config",
+                util::color("blue", "fg"),
+                util::color("white", "fg"),
+            )
+        }
+        return;
+    }
+
+    let path = socket_path();
+
+    if std::fs::symlink_metadata(&path).is_ok() {
+        if !owned(&path) {
+            eprintln!(
+                "{}\"{}\"{} isn't a socket of this user, remove it or set {}XDG_RUNTIME_DIR{}",
+                util::color("green", "fg"),
+                path.display(),
+                util::color("white", "fg"),
+                util::color("blue", "fg"),
+                util::color("white", "fg"),
+            );
+            return;
+        }
+
+        // A socket nobody answers on is left over from a daemon that didn't exit cleanly
+        if UnixStream::connect(&path).is_ok() {
+            eprintln!(
+                "The daemon is already running on {}\"{}\"{}",
+                util::color("green", "fg"),
+                path.display(),
+                util::color("white", "fg"),
+            );
+            return;
+        }
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    let listener = match UnixListener::bind(&path) {
+        Ok(listener) => listener,
+        Err(error) => {
+            eprintln!(
+                "Failed to listen on {}\"{}\"{}: {}{}{}",
+                util::color("green", "fg"),
+                path.display(),
+                util::color("white", "fg"),
+                util::color("red", "fg"),
+                error,
+                util::color("white", "fg"),
+            );
+            return;
+        }
+    };
+
+    // Only this user may connect, the socket can be in /tmp
+    if let Err(error) = std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)) {
+        eprintln!(
+            "Failed to restrict {}\"{}\"{}: {}{}{}",
+            util::color("green", "fg"),
+            path.display(),
+            util::color("white", "fg"),
+            util::color("red", "fg"),
+            error,
+            util::color("white", "fg"),
+        );
+        let _ = std::fs::remove_file(&path);
+        return;
+    }
+
+    std::env::set_var(NO_DAEMON, "1");
+
+    let state = Arc::new(Mutex::new(State {
+        stamps: stamps(&functions::config_path(config_path.clone()).0, &registry),
+        config_path,
+        registries,
+        config,
+        registry
+    }));
+
+    // Rules added to the config later are followed too
+    {
+        let state = Arc::clone(&state);
+
+        std::thread::spawn(move || {
+            let current = || {
+                let mut state = lock(&state);
+
+                // Nothing is loaded while the config or the registry is broken, the errors were reported
+                match state.refresh() {
+                    Ok(()) => state.config.clone(),
+                    Err(()) => None
+                }
+            };
+
+            schedule::run(false, current, |profiles| {
+                let state = lock(&state);
+                functions::load(profiles, force, false, &state.config, &state.registry);
+            });
+        });
+    }
+
+    println!(
+        "Listening on {}\"{}\"{}",
+        util::color("green", "fg"),
+        path.display(),
+        util::color("white", "fg"),
+    );
+
+    for stream in listener.incoming().flatten() {
+        let state = Arc::clone(&state);
+
+        std::thread::spawn(move || serve_client(stream, &state));
+    }
+}
+
+fn exchange(mut stream: UnixStream, message: &Message) -> Result<Response, String> {
+    let failed = |error: std::io::Error| format!("The daemon didn't answer: {}", error);
+
+    stream.set_read_timeout(Some(Duration::from_secs(60))).map_err(failed)?;
+    writeln!(stream, "{}", serde_json::to_string(message).map_err(|error| error.to_string())?).map_err(failed)?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line).map_err(failed)?;
+
+    if line.is_empty() {
+        return Err("The daemon closed the connection without answering".to_string());
+    }
+
+    serde_json::from_str(&line).map_err(|error| format!("The daemon sent an invalid answer: {}", error))
+}
+
+// Send a request to the daemon, None if it isn't running. Once connected the request may have been
+// applied, so failing after that is an error instead of a reason to run the command again.
+pub fn request(message: &Message) -> Option<Result<Response, String>> {
+    let path = socket_path();
+
+    if !owned(&path) {
+        return None;
+    }
+
+    let stream = UnixStream::connect(&path).ok()?;
+    Some(exchange(stream, message))
+}
+
+// Run get, set, load and dump through the daemon if it's running with the same config and registry
+// the command would use. Returns whether it handled the command, otherwise it's run the usual way.
+pub fn forward(subcommand: &SubCommands, config: Option<String>, registries: Vec<String>) -> bool {
+    if std::env::var_os(NO_DAEMON).is_some() {
+        return false;
+    }
+
+    let request = match subcommand {
+        SubCommands::Get(Get { name, .. }) => Request::Get { names: vec![name.to_string()] },
+        SubCommands::Set(Set { name, value, force }) => Request::Set {
+            values: vec![(name.to_string(), value.to_string())],
+            force: *force
+        },
+        // The daemon runs elsewhere, so profiles found relative to here are sent as full paths
+        SubCommands::Load(Load { profiles, force, dry_run: false }) => Request::Load {
+            profiles: profiles
+                .iter()
+                .map(|name| {
+                    profile::find(name, Path::new(""))
+                        .and_then(|path| std::fs::canonicalize(path).ok())
+                        .map(|path| path.display().to_string())
+                        .unwrap_or_else(|| name.to_string())
+                })
+                .collect(),
+            force: *force
+        },
+        SubCommands::Dump(_) => Request::Dump,
+        _ => return false
+    };
+
+    let message = Message { paths: Some(Paths::resolve(config, registries)), request };
+
+    let response = match self::request(&message) {
+        None => return false,
+        Some(Ok(response)) if response.declined => return false,
+        Some(Ok(response)) => response,
+        Some(Err(error)) => {
+            eprintln!("{}{}{}", util::color("red", "fg"), error, util::color("white", "fg"));
+            return true;
+        }
+    };
+
+    for line in response.output {
+        match line {
+            util::Output::Stdout(line) => println!("{}", line),
+            util::Output::Stderr(line) => eprintln!("{}", line)
+        }
+    }
+
+    if let Some(error) = response.error {
+        eprintln!("{}{}{}", util::color("red", "fg"), error, util::color("white", "fg"));
+        return true;
+    }
+
+    match (subcommand, response.values) {
+        (SubCommands::Get(Get { format, .. }), Some(values)) => functions::print_get(&values, *format),
+        (SubCommands::Dump(Dump { format }), Some(values)) => functions::print_dump(values, *format),
+        _ => {}
+    }
+
+    true
+}
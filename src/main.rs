@@ -1,38 +1,120 @@
 use structopt::StructOpt;
 
 mod args;
+#[macro_use]
 mod util;
 mod setting;
 mod functions;
 mod config;
+mod encoding;
+mod format;
+mod registry;
+mod scaffold;
+mod import;
+mod export;
+mod template;
+mod profile;
+mod schedule;
+mod watch;
+mod ipc;
 
-use args::{Args, SubCommands, Set, Get, Load};
+use args::{Args, SubCommands, Set, Get, Load, Dump, List, Schema, Scaffold, Import, Export, Render, Diff, Profile, ProfileCommands, Cycle, Random, Filter, Daemon, Watch};
 
 fn main() {
     let args = Args::from_args();
     let subcommand = args.sub;
 
-    let config = functions::get_config();
-	let registry = functions::get_registry(args.registry);
+    // Commands that don't need the config or the registry
+    match subcommand {
+        SubCommands::Schema(Schema { kind }) => {
+            functions::schema(&kind);
+            return;
+        }
+        SubCommands::Scaffold(Scaffold { file, prefix }) => {
+            scaffold::scaffold(file, prefix);
+            return;
+        }
+        SubCommands::Import(Import { source, file, mapping, output }) => {
+            import::import(&source, file, mapping, output);
+            return;
+        }
+        SubCommands::Diff(Diff { from, to: Some(to) }) => {
+            functions::diff(from, Some(to), None);
+            return;
+        }
+        SubCommands::Profile(Profile { command: ProfileCommands::List(Filter { tag, glob }) }) => {
+            for name in profile::filter(tag.as_deref(), glob.as_deref()) {
+                println!("{}", name);
+            }
+            return;
+        }
+        SubCommands::Profile(Profile { command: ProfileCommands::Current }) => {
+            for name in profile::current() {
+                println!("{}", name);
+            }
+            return;
+        }
+        _ => {}
+    }
 
-	if registry.is_err() {
-		return;
-	}
+    // The daemon only takes the command if it uses the same config and registry
+    if ipc::forward(&subcommand, args.config.clone(), args.registry.clone()) {
+        return;
+    }
+
+    let config = match functions::get_config(args.config.clone()) {
+        Ok(config) => config,
+        Err(_) => return
+    };
 
-	let registry = registry.unwrap();
+	let registry = match functions::get_registry(args.registry.clone()) {
+		Ok(registry) => registry,
+		Err(_) => return
+	};
 
     match subcommand {
-        SubCommands::Set(Set {name, value}) => {
-            functions::set(name, value, &config, &registry);
+        SubCommands::Set(Set {name, value, force}) => {
+            functions::set(name, value, force, &config, &registry);
+        }
+        SubCommands::Get(Get { name, format }) => {
+            functions::get(name, format, &config, &registry.settings);
+        }
+        SubCommands::Load(Load { profiles, force, dry_run }) => {
+            functions::load(profiles, force, dry_run, &config, &registry);
+        }
+        SubCommands::Dump(Dump { format }) => {
+            functions::dump(format, &registry.settings);
+        }
+        SubCommands::List(List { format }) => {
+            functions::list(format, &registry.settings);
+        }
+        SubCommands::Export(Export { format, mapping, output }) => {
+            export::export(&format, mapping, output, &registry.settings);
+        }
+        SubCommands::Render(Render {}) => {
+            functions::render(&registry);
         }
-        SubCommands::Get(Get { name }) => {
-            functions::get(name, true, &config, &registry);
+        SubCommands::Diff(Diff { from, to }) => {
+            functions::diff(from, to, Some(&registry));
         }
-        SubCommands::Load(Load { path }) => {
-            functions::load(path, &config, &registry);
+        SubCommands::Profile(Profile { command }) => match command {
+            ProfileCommands::Next(Cycle { filter: Filter { tag, glob }, force }) => {
+                functions::cycle("next", tag, glob, None, force, &config, &registry);
+            }
+            ProfileCommands::Prev(Cycle { filter: Filter { tag, glob }, force }) => {
+                functions::cycle("prev", tag, glob, None, force, &config, &registry);
+            }
+            ProfileCommands::Random(Random { filter: Filter { tag, glob }, force, seed }) => {
+                functions::cycle("random", tag, glob, seed, force, &config, &registry);
+            }
+            ProfileCommands::List(_) | ProfileCommands::Current => {}
+        },
+        SubCommands::Daemon(Daemon { once, force }) => {
+            ipc::daemon(once, force, args.config, args.registry, config, registry);
         }
-        SubCommands::Dump => {
-            functions::dump(&config, &registry);
+        SubCommands::Watch(Watch {}) => {
+            watch::watch(&config, &registry);
         }
+        SubCommands::Schema(_) | SubCommands::Scaffold(_) | SubCommands::Import(_) => {}
     }
 }
@@ -0,0 +1,362 @@
+//! Handles reading profiles, the JSON files of setting values `load` applies
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+
+use crate::util;
+
+pub type Profile = BTreeMap<String, String>;
+
+// The directory named profiles are looked up in
+pub fn profiles_dir() -> PathBuf {
+    PathBuf::from(util::config_dir()).join("profiles")
+}
+
+// Find a profile, either a path or the name of a profile in the profiles directory. "nord" and
+// "colors/nord" are looked up as "nord.json" and "colors/nord.json" there. Any path that isn't a
+// directory counts, so "/dev/stdin" can be read as well.
+pub fn find(name: &str, base: &Path) -> Option<PathBuf> {
+    let name = util::expand_env_vars(name);
+    let mut candidates: Vec<PathBuf> = vec![base.join(&name)];
+
+    if !name.ends_with(".json") {
+        candidates.push(base.join(format!("{}.json", name)));
+    }
+
+    candidates.push(profiles_dir().join(&name));
+    candidates.push(profiles_dir().join(format!("{}.json", name)));
+
+    candidates.into_iter().find(|path| path.exists() && !path.is_dir())
+}
+
+fn report(path: &Path, error: impl std::fmt::Display) {
+    errln!(
+        "Failed to read profile {}\"{}\"{}: {}{}{}",
+        util::color("green", "fg"),
+        path.display(),
+        util::color("white", "fg"),
+        util::color("red", "fg"),
+        error,
+        util::color("white", "fg"),
+    );
+}
+
+// Read a profile along with the profiles it extends. "extends" names one profile or a list of
+// them, relative to the directory of the profile, their values are applied in order and the ones
+// of the profile itself come last. `stack` holds the profiles being read, to catch cycles.
+fn read_path(path: &Path, stack: &mut Vec<PathBuf>) -> Result<Profile, ()> {
+    let canonical = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+
+    if stack.contains(&canonical) {
+        report(path, "it extends itself");
+        return Err(());
+    }
+
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(error) => {
+            report(path, error);
+            return Err(());
+        }
+    };
+
+    let mut json: serde_json::Map<String, serde_json::Value> = match serde_json::from_str(&contents) {
+        Ok(json) => json,
+        Err(error) => {
+            report(path, error);
+            return Err(());
+        }
+    };
+
+    let extends: Vec<String> = match json.remove("extends") {
+        None => Vec::new(),
+        Some(serde_json::Value::String(name)) => vec![name],
+        Some(serde_json::Value::Array(names)) if names.iter().all(|name| name.is_string()) => names
+            .iter()
+            .filter_map(|name| name.as_str().map(|name| name.to_string()))
+            .collect(),
+        Some(_) => {
+            report(path, "\"extends\" has to be the name of a profile or a list of them");
+            return Err(());
+        }
+    };
+
+    // Tags only matter when picking profiles
+    json.remove("tags");
+
+    let mut values = Profile::new();
+
+    for (name, value) in json {
+        match value {
+            serde_json::Value::String(value) => {
+                values.insert(name, value);
+            }
+            _ => {
+                report(path, format!("the value of \"{}\" has to be a string", name));
+                return Err(());
+            }
+        }
+    }
+
+    let base = path.parent().unwrap_or_else(|| Path::new("."));
+    let mut profile = Profile::new();
+
+    stack.push(canonical);
+
+    for name in extends {
+        let parent = match find(&name, base) {
+            Some(parent) => parent,
+            None => {
+                report(path, format!("the profile \"{}\" it extends doesn't exist", name));
+                stack.pop();
+                return Err(());
+            }
+        };
+
+        match read_path(&parent, stack) {
+            Ok(parent) => profile.extend(parent),
+            Err(_) => {
+                stack.pop();
+                return Err(());
+            }
+        }
+    }
+
+    stack.pop();
+    profile.extend(values);
+
+    Ok(profile)
+}
+
+// Read several profiles into one, later profiles override the values of earlier ones
+pub fn read(names: &[String]) -> Result<Profile, ()> {
+    let mut profile = Profile::new();
+
+    for name in names {
+        let path = match find(name, Path::new("")) {
+            Some(path) => path,
+            None => {
+                errln!(
+                    "Profile {}\"{}\"{} doesn't exist, it's neither a file nor in {}\"{}\"{}",
+                    util::color("green", "fg"),
+                    name,
+                    util::color("white", "fg"),
+                    util::color("green", "fg"),
+                    profiles_dir().display(),
+                    util::color("white", "fg"),
+                );
+                return Err(());
+            }
+        };
+
+        profile.extend(read_path(&path, &mut Vec::new())?);
+    }
+
+    Ok(profile)
+}
+
+// Print what changes going from one profile to another, "+" for added settings, "-" for removed
+// ones and "~" for changed ones. Returns whether there's any difference.
+pub fn diff(from: &Profile, to: &Profile) -> bool {
+    let mut names: Vec<&String> = from.keys().chain(to.keys()).collect();
+    names.sort();
+    names.dedup();
+
+    let mut different = false;
+
+    for name in names {
+        match (from.get(name), to.get(name)) {
+            (None, Some(value)) => outln!(
+                "{}+ {} = \"{}\"{}",
+                util::color("green", "fg"),
+                name,
+                value,
+                util::color("white", "fg"),
+            ),
+            (Some(value), None) => outln!(
+                "{}- {} = \"{}\"{}",
+                util::color("red", "fg"),
+                name,
+                value,
+                util::color("white", "fg"),
+            ),
+            (Some(old), Some(new)) if old != new => outln!(
+                "{}~ {}: \"{}\" -> \"{}\"{}",
+                util::color("yellow", "fg"),
+                name,
+                old,
+                new,
+                util::color("white", "fg"),
+            ),
+            _ => continue
+        }
+
+        different = true;
+    }
+
+    different
+}
+
+// The name of a profile in the profiles directory, or the path of any other profile
+fn name(path: &Path) -> String {
+    let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let dir = std::fs::canonicalize(profiles_dir()).unwrap_or_else(|_| profiles_dir());
+
+    match path.strip_prefix(&dir) {
+        Ok(relative) => relative.with_extension("").display().to_string(),
+        Err(_) => path.display().to_string()
+    }
+}
+
+// Every profile in the profiles directory by name, sorted
+pub fn list() -> Vec<String> {
+    fn collect(dir: &Path, files: &mut Vec<PathBuf>) {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return
+        };
+
+        for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+            if path.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.starts_with('.')) {
+                continue;
+            }
+
+            if path.is_dir() {
+                collect(&path, files);
+            } else if path.extension().and_then(|extension| extension.to_str()) == Some("json") {
+                files.push(path);
+            }
+        }
+    }
+
+    let mut files: Vec<PathBuf> = Vec::new();
+    collect(&profiles_dir(), &mut files);
+
+    let mut names: Vec<String> = files.iter().map(|file| name(file)).collect();
+    names.sort();
+    names
+}
+
+// The tags of a profile, from its "tags" list
+fn tags(name: &str) -> Vec<String> {
+    let path = profiles_dir().join(format!("{}.json", name));
+
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|contents| serde_json::from_str::<serde_json::Value>(&contents).ok())
+        .and_then(|json| json.get("tags").cloned())
+        .and_then(|tags| serde_json::from_value(tags).ok())
+        .unwrap_or_default()
+}
+
+// Turn a glob into a regex matching whole names, "*" doesn't cross a "/" while "**" does
+fn glob(pattern: &str) -> Regex {
+    let mut regex = String::from("^");
+    let mut chars = pattern.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                regex.push_str(".*");
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            c => regex.push_str(&regex::escape(&c.to_string()))
+        }
+    }
+
+    regex.push('$');
+    Regex::new(&regex).unwrap()
+}
+
+// The profiles of the profiles directory with the tag and matching the glob
+pub fn filter(tag: Option<&str>, pattern: Option<&str>) -> Vec<String> {
+    let pattern = pattern.map(glob);
+
+    list()
+        .into_iter()
+        .filter(|name| pattern.as_ref().is_none_or(|pattern| pattern.is_match(name)))
+        .filter(|name| tag.is_none_or(|tag| tags(name).iter().any(|other| other == tag)))
+        .collect()
+}
+
+fn state_file() -> PathBuf {
+    PathBuf::from(util::state_dir()).join("profile.json")
+}
+
+// The profiles loaded last, as names or paths
+pub fn current() -> Vec<String> {
+    std::fs::read_to_string(state_file())
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+// Remember the profiles that were loaded, for cycling through profiles and for `profile current`
+pub fn set_current(names: &[String]) {
+    let names: Vec<String> = names
+        .iter()
+        .map(|profile| find(profile, Path::new("")).map(|path| name(&path)).unwrap_or_else(|| profile.to_string()))
+        .collect();
+
+    let written = std::fs::create_dir_all(util::state_dir())
+        .and_then(|_| std::fs::write(state_file(), serde_json::to_string(&names).unwrap_or_default() + "\n"));
+
+    if let Err(error) = written {
+        errln!(
+            "Failed to write {}\"{}\"{}: {}{}{}",
+            util::color("green", "fg"),
+            state_file().display(),
+            util::color("white", "fg"),
+            util::color("red", "fg"),
+            error,
+            util::color("white", "fg"),
+        );
+    }
+}
+
+// A small splitmix64 generator, enough to pick profiles without pulling in a crate
+fn random(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+// Pick the profile after or before the current one, wrapping around, or a random one. Without a
+// seed the clock is used and the current profile is skipped, with one the pick only depends on the
+// seed and the candidates so it can be reproduced.
+pub fn pick(candidates: &[String], action: &str, seed: Option<u64>) -> Option<String> {
+    if candidates.is_empty() {
+        return None;
+    }
+
+    let current = current().last().and_then(|current| candidates.iter().position(|name| name == current));
+
+    let index = match (action, current) {
+        ("next", Some(i)) => (i + 1) % candidates.len(),
+        ("next", None) => 0,
+        ("prev", Some(i)) => (i + candidates.len() - 1) % candidates.len(),
+        ("prev", None) => candidates.len() - 1,
+        (_, current) => match seed {
+            Some(seed) => (random(seed) % candidates.len() as u64) as usize,
+            None => {
+                let seed = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map(|time| time.as_nanos() as u64)
+                    .unwrap_or_default();
+
+                match current {
+                    // Skip the current profile by picking among the others
+                    Some(i) if candidates.len() > 1 => (i + 1 + (random(seed) % (candidates.len() as u64 - 1)) as usize) % candidates.len(),
+                    _ => (random(seed) % candidates.len() as u64) as usize
+                }
+            }
+        }
+    };
+
+    Some(candidates[index].to_string())
+}
@@ -0,0 +1,699 @@
+//! Handles reading registry files in the formats exconman understands
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::de::{Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::de::value::{MapAccessDeserializer, SeqAccessDeserializer};
+use schemars::JsonSchema;
+use serde_derive::Deserialize;
+
+use crate::util;
+use crate::encoding::Encoding;
+use crate::setting::{Setting, Replace, Condition};
+use crate::template::Template;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    Toml,
+    Yaml
+}
+
+impl Format {
+    // Pick the format from the extension of a path, anything unknown is read as JSON
+    pub fn from_path(path: &Path) -> Format {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => Format::Toml,
+            Some("yaml") | Some("yml") => Format::Yaml,
+            _ => Format::Json
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Format::Json => "JSON",
+            Format::Toml => "TOML",
+            Format::Yaml => "YAML"
+        }
+    }
+}
+
+// A registry file is either a list of settings, or a table holding them under "settings" along
+// with a header. TOML only has the latter since its top level is always a table.
+#[derive(Debug, Default)]
+pub struct RegistryFile {
+    // Prefix for the names of the settings, overrides the one derived from the directory structure
+    pub namespace: Option<String>,
+    pub defaults: Defaults,
+    // Registry files or directories whose settings come before the ones of this file, relative
+    // paths are resolved against the directory of this file
+    pub include: Vec<String>,
+    pub settings: Vec<Setting>,
+    pub templates: Vec<Template>
+}
+
+// The settings and templates read from one or more registry files
+#[derive(Debug, Default)]
+pub struct Registry {
+    pub settings: Vec<Setting>,
+    pub templates: Vec<Template>,
+    // Every `when` that was evaluated and whether it held, including the ones of settings another
+    // variant replaced, so the daemon can tell when the registry has to be read again
+    pub conditions: Vec<(Condition, bool)>
+}
+
+// Values shared by all settings of a file, used for the fields a setting leaves out
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Defaults {
+    pub file: Option<String>,
+    pub replace: Option<Replace>,
+    pub read_value_path: Option<bool>,
+    pub multiple: Option<bool>,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    pub encoding: Option<Encoding>,
+    pub when: Option<Condition>,
+    pub order: Option<i64>
+}
+
+impl Defaults {
+    fn apply(&self, setting: &mut Setting) {
+        if setting.file.is_empty() {
+            if let Some(file) = &self.file {
+                setting.file = file.to_string();
+            }
+        }
+
+        setting.replace = setting.replace.take().or_else(|| self.replace.clone());
+        setting.read_value_path = setting.read_value_path.or(self.read_value_path);
+        setting.multiple = setting.multiple.or(self.multiple);
+        setting.before = setting.before.take().or_else(|| self.before.clone());
+        setting.after = setting.after.take().or_else(|| self.after.clone());
+        setting.encoding = setting.encoding.or(self.encoding);
+        setting.when = setting.when.take().or_else(|| self.when.clone());
+        setting.order = setting.order.or(self.order);
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Document {
+    namespace: Option<String>,
+    #[serde(default)]
+    defaults: Defaults,
+    #[serde(default)]
+    include: Vec<String>,
+    #[serde(default)]
+    settings: Vec<Setting>,
+    #[serde(default)]
+    templates: Vec<Template>
+}
+
+// The shapes `RegistryFile` accepts, only used to describe registry files in a JSON Schema
+#[derive(JsonSchema)]
+#[serde(untagged)]
+#[schemars(rename = "Registry")]
+#[allow(dead_code, clippy::large_enum_variant)]
+enum RegistryFileSchema {
+    Settings(Vec<Setting>),
+    Document(Document)
+}
+
+pub fn schema() -> schemars::schema::RootSchema {
+    schemars::schema_for!(RegistryFileSchema)
+}
+
+impl<'de> Deserialize<'de> for RegistryFile {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct RegistryFileVisitor;
+
+        impl<'de> Visitor<'de> for RegistryFileVisitor {
+            type Value = RegistryFile;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a list of settings or a table with a \"settings\" list")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+                let settings = Vec::<Setting>::deserialize(SeqAccessDeserializer::new(seq))?;
+
+                Ok(RegistryFile { settings, ..RegistryFile::default() })
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+                let document = Document::deserialize(MapAccessDeserializer::new(map))?;
+
+                Ok(RegistryFile {
+                    namespace: document.namespace,
+                    defaults: document.defaults,
+                    include: document.include,
+                    settings: document.settings,
+                    templates: document.templates
+                })
+            }
+        }
+
+        deserializer.deserialize_any(RegistryFileVisitor)
+    }
+}
+
+// Parse the contents of a registry file. The error is the path to the value that failed, such as
+// "settings[2].replace", and the message of the format which carries the line and column.
+pub fn parse(contents: &str, format: Format) -> Result<RegistryFile, (serde_path_to_error::Path, String)> {
+    fn split<E: std::fmt::Display>(error: serde_path_to_error::Error<E>) -> (serde_path_to_error::Path, String) {
+        (error.path().clone(), error.inner().to_string())
+    }
+
+    match format {
+        Format::Json => {
+            let mut deserializer = serde_json::Deserializer::from_str(contents);
+            serde_path_to_error::deserialize(&mut deserializer).map_err(split)
+        }
+        Format::Toml => {
+            let deserializer = toml::Deserializer::new(contents);
+
+            // Worded like the errors of the other formats, instead of the snippet toml prints
+            serde_path_to_error::deserialize(deserializer).map_err(|error| {
+                let message = error.inner().message().replace('\n', ", ");
+                let message = match error.inner().span() {
+                    Some(span) => {
+                        let before = &contents[..span.start];
+                        let line = before.matches('\n').count() + 1;
+                        let column = before.chars().rev().take_while(|&c| c != '\n').count() + 1;
+
+                        format!("{} at line {} column {}", message, line, column)
+                    }
+                    None => message
+                };
+
+                (error.path().clone(), message)
+            })
+        }
+        Format::Yaml => {
+            let deserializer = serde_yaml::Deserializer::from_str(contents);
+
+            // serde_yaml starts the message with its own path, such as "settings[0]: ", which would
+            // repeat the one already given. Keep the rest, it ends with the line and column.
+            serde_path_to_error::deserialize(deserializer).map_err(|error| {
+                let message = error.inner().to_string();
+                let located = error.inner().location().is_some();
+                let message = match message.split_once(": ") {
+                    Some((path, rest)) if located && !path.contains(char::is_whitespace) => rest.to_string(),
+                    _ => message
+                };
+
+                (error.path().clone(), message)
+            })
+        }
+    }
+}
+
+// Describe where a parse error happened, naming the setting and the field when possible
+fn describe(contents: &str, format: Format, path: &serde_path_to_error::Path) -> String {
+    use serde_path_to_error::Segment;
+
+    let segments: Vec<&Segment> = path.iter().collect();
+
+    // Settings are either at the top level or in "settings"
+    let (index, fields) = match segments.as_slice() {
+        [Segment::Seq { index }, fields @ ..] => (*index, fields),
+        [Segment::Map { key }, Segment::Seq { index }, fields @ ..] if key == "settings" => (*index, fields),
+        _ => {
+            return match path.to_string().as_str() {
+                "." | "?" => String::new(),
+                path => format!("field {}\"{}\"{}: ", util::color("blue", "fg"), path, util::color("white", "fg"))
+            };
+        }
+    };
+
+    // The name is looked up in a loose parse of the file, the strict one just failed
+    let value: Option<serde_json::Value> = match format {
+        Format::Json => serde_json::from_str(contents).ok(),
+        Format::Toml => toml::from_str::<toml::Value>(contents).ok().and_then(|value| serde_json::to_value(value).ok()),
+        Format::Yaml => serde_yaml::from_str(contents).ok()
+    };
+
+    let name = value.as_ref().and_then(|value| {
+        value.get("settings").unwrap_or(value)
+            .get(index)?
+            .get("name")?
+            .as_str()
+            .map(|name| name.to_string())
+    });
+
+    let mut description = match name {
+        Some(name) => format!("setting {}\"{}\"{}", util::color("green", "fg"), name, util::color("white", "fg")),
+        None => format!("setting #{}", index + 1)
+    };
+
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|segment| match segment {
+            Segment::Seq { index } => format!("[{}]", index),
+            segment => segment.to_string()
+        })
+        .collect();
+
+    if !fields.is_empty() {
+        description.push_str(&format!(
+            ", field {}\"{}\"{}",
+            util::color("blue", "fg"),
+            fields.join(".").replace(".[", "["),
+            util::color("white", "fg")
+        ));
+    }
+
+    description + ": "
+}
+
+// Read and parse a single registry file, prefixing the names of its settings with the namespace
+// unless the file declares its own. `stack` holds the files being read, to catch include cycles.
+pub fn read_file(path: &Path, namespace: Option<&str>, stack: &mut Vec<PathBuf>) -> Result<Registry, ()> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(error) => {
+            errln!(
+                "Failed to read {}\"{}\"{}: {}{}{}",
+                util::color("green", "fg"),
+                path.display(),
+                util::color("white", "fg"),
+                util::color("red", "fg"),
+                error,
+                util::color("white", "fg")
+            );
+            return Err(());
+        }
+    };
+
+    let format = Format::from_path(path);
+
+    let registry = match parse(&contents, format) {
+        Ok(registry) => registry,
+        Err((error_path, error)) => {
+            errln!(
+                "{} Error in {}\"{}\"{}: {}{}{}{}{}",
+                format.name(),
+                util::color("green", "fg"),
+                path.display(),
+                util::color("white", "fg"),
+                describe(&contents, format, &error_path),
+                util::color("red", "fg"),
+                error,
+                util::color("white", "fg"),
+                util::did_you_mean(&error),
+            );
+            return Err(());
+        }
+    };
+
+    let namespace = registry.namespace.as_deref().or(namespace);
+    let mut settings: Vec<Setting> = Vec::new();
+    let mut failed = false;
+
+    for setting in registry.settings {
+        let name = setting.name.to_string();
+
+        match setting.expand() {
+            Ok(mut expanded) => settings.append(&mut expanded),
+            Err(error) => {
+                errln!(
+                    "Parameter Error in setting {}\"{}\"{} in {}\"{}\"{}: {}{}{}",
+                    util::color("green", "fg"),
+                    name,
+                    util::color("white", "fg"),
+                    util::color("green", "fg"),
+                    path.display(),
+                    util::color("white", "fg"),
+                    util::color("red", "fg"),
+                    error,
+                    util::color("white", "fg"),
+                );
+                failed = true;
+            }
+        }
+    }
+
+    let mut conditions: Vec<(Condition, bool)> = Vec::new();
+
+    for setting in settings.iter_mut() {
+        setting.source = path.display().to_string();
+        registry.defaults.apply(setting);
+
+        // Evaluated before merging, so a variant for another machine can't replace this one
+        if let Some(when) = &setting.when {
+            let holds = when.holds();
+            setting.skipped = !holds;
+            conditions.push((when.clone(), holds));
+        }
+
+        if let Some(namespace) = namespace {
+            // Names that already carry the prefix are left alone
+            if !namespace.is_empty() && !setting.name.starts_with(&format!("{}.", namespace)) {
+                setting.name = format!("{}.{}", namespace, setting.name);
+            }
+        }
+
+        if setting.file.is_empty() {
+            errln!(
+                "Setting {}\"{}\"{} in {}\"{}\"{} has no {}file{} and the file has no default for it",
+                util::color("green", "fg"),
+                setting.name,
+                util::color("white", "fg"),
+                util::color("green", "fg"),
+                path.display(),
+                util::color("white", "fg"),
+                util::color("blue", "fg"),
+                util::color("white", "fg"),
+            );
+            failed = true;
+        }
+    }
+
+    if failed {
+        return Err(());
+    }
+
+    // Templates are resolved against the directory of this file, like includes
+    let base = path.parent().unwrap_or_else(|| Path::new("."));
+
+    let templates: Vec<Template> = registry.templates
+        .into_iter()
+        .map(|mut template| {
+            template.source = base.join(util::expand_env_vars(&template.source)).display().to_string();
+            template.target = base.join(util::expand_env_vars(&template.target)).display().to_string();
+            template.origin = path.display().to_string();
+            template
+        })
+        .collect();
+
+    let own = Registry { settings, templates, conditions };
+
+    if registry.include.is_empty() {
+        return Ok(own);
+    }
+
+    // Included settings come first so the ones of this file override them
+    let mut included = Registry::default();
+
+    stack.push(canonical(path));
+
+    for include in &registry.include {
+        let include = base.join(util::expand_env_vars(include));
+
+        match read_path(&include, stack) {
+            Ok(other) => merge(&mut included, other, false),
+            Err(_) => {
+                stack.pop();
+                return Err(());
+            }
+        }
+    }
+
+    stack.pop();
+    merge(&mut included, own, false);
+
+    Ok(included)
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+// Whether a directory entry should be skipped, hidden files and the leftovers of editors are never
+// registry files
+fn is_ignored(path: &Path) -> bool {
+    let name = match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => name,
+        None => return true
+    };
+
+    name.starts_with('.')
+        || name.starts_with('#')
+        || name.ends_with('~')
+        || name.ends_with(".bak")
+        || name.ends_with(".swp")
+        || name.ends_with(".orig")
+}
+
+fn is_registry_file(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|extension| extension.to_str()),
+        Some("json") | Some("toml") | Some("yaml") | Some("yml")
+    )
+}
+
+// Collect the registry files under a directory, recursing into subdirectories
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), ()> {
+    let report = |path: &Path, error: std::io::Error| {
+        errln!(
+            "Failed to read {}\"{}\"{}: {}{}{}",
+            util::color("green", "fg"),
+            path.display(),
+            util::color("white", "fg"),
+            util::color("red", "fg"),
+            error,
+            util::color("white", "fg")
+        );
+    };
+
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(error) => {
+            report(dir, error);
+            return Err(());
+        }
+    };
+
+    for entry in entries {
+        let path = match entry {
+            Ok(entry) => entry.path(),
+            Err(error) => {
+                report(dir, error);
+                return Err(());
+            }
+        };
+
+        if is_ignored(&path) {
+            continue;
+        }
+
+        // Follows symlinks, so linked in registries work
+        match std::fs::metadata(&path) {
+            Ok(metadata) if metadata.is_dir() => collect_files(&path, files)?,
+            Ok(_) => {
+                if is_registry_file(&path) {
+                    files.push(path);
+                }
+            }
+            Err(error) => {
+                report(&path, error);
+                return Err(());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Derive a namespace from the path of a registry file inside a registry directory,
+// "bspwm/colors.json" becomes "bspwm.colors"
+fn namespace(dir: &Path, file: &Path) -> String {
+    let relative = file.strip_prefix(dir).unwrap_or(file);
+
+    relative
+        .with_extension("")
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy().to_string())
+        .collect::<Vec<String>>()
+        .join(".")
+}
+
+// Read every registry file under a directory, in the order of their paths
+pub fn read_dir(dir: &Path, stack: &mut Vec<PathBuf>) -> Result<Registry, ()> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    collect_files(dir, &mut files)?;
+    files.sort();
+
+    let mut registry = Registry::default();
+
+    for file in files {
+        let namespace = namespace(dir, &file);
+        merge(&mut registry, read_file(&file, Some(&namespace), stack)?, true);
+    }
+
+    Ok(registry)
+}
+
+// Read a registry file or directory
+pub fn read_path(path: &Path, stack: &mut Vec<PathBuf>) -> Result<Registry, ()> {
+    if stack.contains(&canonical(path)) {
+        errln!(
+            "Registry {}\"{}\"{} includes itself",
+            util::color("green", "fg"),
+            path.display(),
+            util::color("white", "fg"),
+        );
+        return Err(());
+    }
+
+    match std::fs::metadata(path) {
+        Err(error) => {
+            errln!(
+                "Failed to read {}\"{}\"{}: {}{}{}",
+                util::color("green", "fg"),
+                path.display(),
+                util::color("white", "fg"),
+                util::color("red", "fg"),
+                error,
+                util::color("white", "fg")
+            );
+            Err(())
+        }
+        Ok(metadata) => {
+            if metadata.is_dir() {
+                stack.push(canonical(path));
+                let registry = read_dir(path, stack);
+                stack.pop();
+                registry
+            } else {
+                read_file(path, None, stack)
+            }
+        }
+    }
+}
+
+// The registry of a layer directory such as "~/.config/exconman", either one of the registry
+// files or the registry directory
+pub fn find_in_layer(layer: &Path) -> Option<PathBuf> {
+    ["registry.json", "registry.toml", "registry.yaml", "registry.yml", "registry"]
+        .iter()
+        .map(|name| layer.join(name))
+        .find(|path| path.exists())
+}
+
+// The default layers, later ones override the settings of earlier ones
+pub fn default_layers() -> Vec<PathBuf> {
+    let mut layers: Vec<PathBuf> = vec![PathBuf::from("/etc/xdg/exconman")];
+
+    let config_dir = PathBuf::from(util::config_dir());
+    layers.push(config_dir.clone());
+
+    if let Some(hostname) = util::hostname() {
+        layers.push(config_dir.join("hosts").join(hostname));
+    }
+
+    layers
+}
+
+impl Registry {
+    // Whether a `when` turned out differently than when the registry was read
+    pub fn conditions_changed(&self) -> bool {
+        self.conditions.iter().any(|(when, held)| when.holds() != *held)
+    }
+}
+
+// Add settings and templates to a registry, a setting with a name that's already taken replaces the
+// earlier one and so does a template with a target that's already taken. A setting whose `when`
+// doesn't hold never replaces one whose `when` holds, so variants for different machines can come
+// in any order. `warn` reports the replacement, for duplicates that likely aren't intended.
+pub fn merge(registry: &mut Registry, other: Registry, warn: bool) {
+    registry.conditions.extend(other.conditions);
+
+    for setting in other.settings {
+        match registry.settings.iter().position(|existing| existing.name == setting.name) {
+            Some(i) => {
+                let existing = &registry.settings[i];
+
+                if setting.skipped && !existing.skipped {
+                    continue;
+                }
+
+                if warn && !setting.skipped && !existing.skipped {
+                    errln!(
+                        "Setting {}\"{}\"{} from {}\"{}\"{} overrides the one from {}\"{}\"{}",
+                        util::color("green", "fg"),
+                        setting.name,
+                        util::color("white", "fg"),
+                        util::color("green", "fg"),
+                        setting.source,
+                        util::color("white", "fg"),
+                        util::color("green", "fg"),
+                        existing.source,
+                        util::color("white", "fg"),
+                    );
+                }
+                registry.settings[i] = setting;
+            }
+            None => registry.settings.push(setting)
+        }
+    }
+
+    for template in other.templates {
+        match registry.templates.iter().position(|existing| existing.target == template.target) {
+            Some(i) => {
+                if warn {
+                    errln!(
+                        "Template for {}\"{}\"{} from {}\"{}\"{} overrides the one from {}\"{}\"{}",
+                        util::color("green", "fg"),
+                        template.target,
+                        util::color("white", "fg"),
+                        util::color("green", "fg"),
+                        template.origin,
+                        util::color("white", "fg"),
+                        util::color("green", "fg"),
+                        registry.templates[i].origin,
+                        util::color("white", "fg"),
+                    );
+                }
+                registry.templates[i] = template;
+            }
+            None => registry.templates.push(template)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two variants of "mon", one for this machine and one for another, in either order
+    fn read_variants(name: &str, this_first: bool) -> Registry {
+        let hostname = util::hostname().expect("the test needs a hostname");
+        let this = serde_json::json!({ "name": "mon", "file": "/dev/null", "pattern": "^mon", "substitute": "mon = {value}", "when": { "hostname": [hostname] } });
+        let other = serde_json::json!({ "name": "mon", "file": "/dev/null", "pattern": "^mon", "substitute": "other = {value}", "when": { "hostname": ["not-this-machine"] } });
+
+        let settings = if this_first { vec![this, other] } else { vec![other, this] };
+        let path = std::env::temp_dir().join(format!("exconman-test-{}-{}.json", name, std::process::id()));
+
+        std::fs::write(&path, serde_json::to_string(&settings).unwrap()).unwrap();
+        let other = read_file(&path, None, &mut Vec::new());
+        std::fs::remove_file(&path).unwrap();
+
+        // Like `get_registry`, which merges every registry path into an empty registry
+        let mut registry = Registry::default();
+        merge(&mut registry, other.unwrap(), false);
+        registry
+    }
+
+    #[test]
+    fn variant_that_holds_wins_in_either_order() {
+        for this_first in [true, false] {
+            let registry = read_variants(&format!("variants-{}", this_first), this_first);
+
+            assert_eq!(registry.settings.len(), 1);
+            assert!(!registry.settings[0].skipped);
+            assert_eq!(registry.settings[0].substitute, "mon = {value}");
+        }
+    }
+
+    #[test]
+    fn yaml_errors_dont_repeat_the_path() {
+        let contents = "settings:\n  - name: b\n    file: /dev/null\n    pattern: [x]\n    substitute: \"{value}\"\n";
+        let (path, message) = parse(contents, Format::Yaml).err().unwrap();
+
+        assert_eq!(path.to_string(), "settings[0].pattern");
+        assert_eq!(message, "a region needs exactly 2 regexes, [start, end], got 1 at line 4 column 14");
+    }
+}
@@ -0,0 +1,199 @@
+//! Handles generating candidate registry entries from an existing config file
+
+use regex::Regex;
+use serde_derive::Serialize;
+
+use crate::util;
+
+// A key/value line found in a config file
+struct Candidate {
+    key: String,
+    // Everything before the value, for example "bspc config normal_border_color "
+    prefix: String,
+    // Quotes around the value, kept out of the value itself
+    quote: &'static str
+}
+
+// A generated registry entry, the fields in the order the shipped registry files use
+#[derive(Serialize)]
+struct Entry {
+    name: String,
+    file: String,
+    pattern: String,
+    substitute: String
+}
+
+// The shapes of key/value lines we know about, the first capture is the text before the value
+// and the second one the key
+fn shapes() -> Vec<Regex> {
+    [
+        r"^(\s*bspc\s+config\s+(\S+)\s+)\S",
+        r"^(\s*set\s+(\$\S+)\s+)\S",
+        r"^(\s*([A-Za-z0-9_.*-]+)\s*=\s*)\S",
+        r"^(\s*([A-Za-z0-9_.*-]+)\s*:\s*)\S"
+    ]
+    .iter()
+    .map(|shape| Regex::new(shape).unwrap())
+    .collect()
+}
+
+fn is_comment(line: &str) -> bool {
+    let line = line.trim_start();
+
+    line.is_empty()
+        || line.starts_with('#')
+        || line.starts_with(';')
+        || line.starts_with("//")
+        || line.starts_with('!')
+        || line.starts_with('[')
+}
+
+fn find(line: &str, shapes: &[Regex]) -> Option<Candidate> {
+    if is_comment(line) {
+        return None;
+    }
+
+    let captures = shapes.iter().find_map(|shape| shape.captures(line))?;
+    let prefix = captures[1].to_string();
+    let value = line[prefix.len()..].trim_end();
+
+    let quote = ["\"", "'"]
+        .iter()
+        .find(|quote| value.len() >= 2 && value.starts_with(*quote) && value.ends_with(*quote))
+        .copied()
+        .unwrap_or("");
+
+    Some(Candidate {
+        key: captures[2].to_string(),
+        prefix,
+        quote
+    })
+}
+
+// Turn a key into a setting name, "*.color0" becomes "color0" and "$bg" becomes "bg"
+fn setting_name(key: &str) -> String {
+    let name: String = key
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-' || *c == '.')
+        .collect();
+
+    name.trim_matches('.').to_string()
+}
+
+// Print registry entries for the key/value lines of a file, as a JSON list
+pub fn scaffold(path: String, prefix: Option<String>) {
+    let file = match std::fs::read_to_string(util::expand_env_vars(&path)) {
+        Ok(file) => file,
+        Err(error) => {
+            eprintln!(
+                "Failed to read file {}\"{}\"{}: {}{}{}",
+                util::color("green", "fg"),
+                path,
+                util::color("white", "fg"),
+                util::color("red", "fg"),
+                error,
+                util::color("white", "fg"),
+            );
+            return;
+        }
+    };
+
+    // Point at the file the way a registry shared between users would
+    let home = util::expand_env_vars("~");
+    let path = std::fs::canonicalize(util::expand_env_vars(&path))
+        .map(|path| path.display().to_string())
+        .unwrap_or(path);
+    let registry_path = match path.strip_prefix(&home) {
+        Some(rest) if home != "~" && rest.starts_with('/') => format!("~{}", rest),
+        _ => path.to_string()
+    };
+
+    let shapes = shapes();
+    let whitespace = Regex::new(r"\s+").unwrap();
+    let mut names: Vec<String> = Vec::new();
+    let mut settings: Vec<Entry> = Vec::new();
+
+    for line in file.split('\n') {
+        let candidate = match find(line, &shapes) {
+            Some(candidate) => candidate,
+            None => continue
+        };
+
+        let mut name = setting_name(&candidate.key);
+
+        if let Some(prefix) = &prefix {
+            name = format!("{}.{}", prefix, name);
+        }
+
+        // A key set several times, for example in different sections, would get the same pattern
+        if names.contains(&name) {
+            eprintln!(
+                "Skipping another {}\"{}\"{} line, the pattern of the first one would match it as well",
+                util::color("green", "fg"),
+                candidate.key,
+                util::color("white", "fg"),
+            );
+            continue;
+        }
+
+        let indentation = &candidate.prefix[..candidate.prefix.len() - candidate.prefix.trim_start().len()];
+        let pattern = format!(
+            r"^\s*{}.*",
+            whitespace.replace_all(&regex::escape(candidate.prefix.trim_start()), r"\s+")
+        );
+        let substitute = format!(
+            "{}{}{}{{value}}{}",
+            indentation,
+            candidate.prefix.trim_start(),
+            candidate.quote,
+            candidate.quote
+        );
+
+        names.push(name.to_string());
+        settings.push(Entry {
+            name,
+            file: registry_path.to_string(),
+            pattern,
+            substitute
+        });
+    }
+
+    match serde_json::to_string_pretty(&settings) {
+        Ok(json) => println!("{}", json),
+        Err(_) => eprintln!("Failed to generate JSON")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key_and_prefix(line: &str) -> Option<(String, String, &'static str)> {
+        find(line, &shapes()).map(|candidate| (candidate.key, candidate.prefix, candidate.quote))
+    }
+
+    #[test]
+    fn find_recognizes_the_known_shapes() {
+        assert_eq!(
+            key_and_prefix("bspc config border_width 2"),
+            Some(("border_width".to_string(), "bspc config border_width ".to_string(), ""))
+        );
+        assert_eq!(key_and_prefix("set $bg \"#000000\""), Some(("$bg".to_string(), "set $bg ".to_string(), "\"")));
+        assert_eq!(key_and_prefix("  font = 'Mono 12'"), Some(("font".to_string(), "  font = ".to_string(), "'")));
+        assert_eq!(key_and_prefix("*.color0: #111111"), Some(("*.color0".to_string(), "*.color0: ".to_string(), "")));
+    }
+
+    #[test]
+    fn find_skips_comments_and_sections() {
+        for line in &["", "# font = Mono", "; font = Mono", "// font = Mono", "! *.color0: #000000", "[colors]"] {
+            assert!(key_and_prefix(line).is_none(), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn setting_name_keeps_only_name_characters() {
+        assert_eq!(setting_name("*.color0"), "color0");
+        assert_eq!(setting_name("$bg"), "bg");
+        assert_eq!(setting_name("URxvt.font"), "URxvt.font");
+    }
+}
@@ -0,0 +1,256 @@
+//! Handles picking profiles by the time of day and other conditions, for `exconman daemon`
+
+use std::time::Duration;
+
+use chrono::{Datelike, Local, NaiveDate, Timelike};
+use schemars::JsonSchema;
+use serde_derive::{Serialize, Deserialize};
+
+use crate::util;
+use crate::config::Config;
+use crate::setting::Condition;
+
+// Profiles to load while a rule holds. A rule without "from" and "to" holds all day.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    pub profiles: Vec<String>, // Loaded in order, like `exconman load`
+    pub from: Option<String>, // "08:00", "sunrise" or "sunset", optionally with an offset in minutes like "sunset-30"
+    pub to: Option<String>, // Same as `from`, a range can wrap around midnight
+    pub when: Option<Condition>, // Default: None, the rule always holds
+}
+
+// Coordinates for working out sunrise and sunset
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Location {
+    pub latitude: f64,
+    pub longitude: f64
+}
+
+// Sunrise and sunset of a day in minutes after midnight UTC, None during polar day and night.
+// Uses the NOAA approximation, which is within a minute or two.
+fn sun_times(date: NaiveDate, location: Location) -> Option<(f64, f64)> {
+    let gamma = 2.0 * std::f64::consts::PI / 365.0 * (date.ordinal() as f64 - 1.0);
+
+    let equation_of_time = 229.18 * (0.000075 + 0.001868 * gamma.cos() - 0.032077 * gamma.sin()
+        - 0.014615 * (2.0 * gamma).cos() - 0.040849 * (2.0 * gamma).sin());
+
+    let declination = 0.006918 - 0.399912 * gamma.cos() + 0.070257 * gamma.sin()
+        - 0.006758 * (2.0 * gamma).cos() + 0.000907 * (2.0 * gamma).sin()
+        - 0.002697 * (3.0 * gamma).cos() + 0.00148 * (3.0 * gamma).sin();
+
+    let latitude = location.latitude.to_radians();
+    let cos_hour_angle = 90.833_f64.to_radians().cos() / (latitude.cos() * declination.cos())
+        - latitude.tan() * declination.tan();
+
+    if !(-1.0..=1.0).contains(&cos_hour_angle) {
+        return None;
+    }
+
+    let hour_angle = cos_hour_angle.acos().to_degrees();
+
+    Some((
+        720.0 - 4.0 * (location.longitude + hour_angle) - equation_of_time,
+        720.0 - 4.0 * (location.longitude - hour_angle) - equation_of_time
+    ))
+}
+
+// Turn a time such as "08:00" or "sunset-30" into minutes after local midnight
+fn minutes(time: &str, location: Option<Location>) -> Result<Option<i64>, String> {
+    let time = time.trim();
+    let (base, offset) = match time.find(['+', '-']) {
+        Some(i) => (&time[..i], Some(&time[i..])),
+        None => (time, None)
+    };
+
+    let offset: i64 = match offset {
+        Some(offset) => offset
+            .trim_start_matches('+')
+            .parse()
+            .map_err(|_| format!("\"{}\" isn't an offset in minutes", offset))?,
+        None => 0
+    };
+
+    let base = match base {
+        "sunrise" | "sunset" => {
+            let location = location.ok_or_else(|| format!("\"{}\" needs a \"location\" in the config", base))?;
+            let now = Local::now();
+            let utc_offset = now.offset().local_minus_utc() as f64 / 60.0;
+
+            match sun_times(now.date_naive(), location) {
+                Some((sunrise, sunset)) => {
+                    let utc = if base == "sunrise" { sunrise } else { sunset };
+                    (utc + utc_offset).round() as i64
+                }
+                None => return Ok(None)
+            }
+        }
+        _ => {
+            let (hours, minutes) = base
+                .split_once(':')
+                .and_then(|(hours, minutes)| Some((hours.parse::<i64>().ok()?, minutes.parse::<i64>().ok()?)))
+                .filter(|(hours, minutes)| (0..24).contains(hours) && (0..60).contains(minutes))
+                .ok_or_else(|| format!("\"{}\" isn't a time like \"08:00\", \"sunrise\" or \"sunset\"", time))?;
+
+            hours * 60 + minutes
+        }
+    };
+
+    Ok(Some((base + offset).rem_euclid(24 * 60)))
+}
+
+impl Rule {
+    pub fn holds(&self, location: Option<Location>) -> Result<bool, String> {
+        if self.when.as_ref().is_some_and(|when| !when.holds()) {
+            return Ok(false);
+        }
+
+        let (from, to) = match (&self.from, &self.to) {
+            (None, None) => return Ok(true),
+            (Some(from), Some(to)) => (minutes(from, location)?, minutes(to, location)?),
+            _ => return Err("\"from\" and \"to\" have to be given together".to_string())
+        };
+
+        // The sun doesn't rise or set today
+        let (from, to) = match (from, to) {
+            (Some(from), Some(to)) => (from, to),
+            _ => return Ok(false)
+        };
+
+        let now = Local::now();
+        let now = (now.hour() * 60 + now.minute()) as i64;
+
+        if from <= to {
+            Ok(from <= now && now < to)
+        } else {
+            Ok(now >= from || now < to)
+        }
+    }
+}
+
+// The profiles of the last rule that holds, later rules override earlier ones like in the registry
+pub fn pick(config: &Config) -> Result<Option<Vec<String>>, ()> {
+    let mut picked: Option<Vec<String>> = None;
+    let mut failed = false;
+
+    for (i, rule) in config.schedule.iter().flatten().enumerate() {
+        match rule.holds(config.location) {
+            Ok(true) => picked = Some(rule.profiles.clone()),
+            Ok(false) => {}
+            Err(error) => {
+                eprintln!(
+                    "Error in schedule rule #{}: {}{}{}",
+                    i + 1,
+                    util::color("red", "fg"),
+                    error,
+                    util::color("white", "fg"),
+                );
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        return Err(());
+    }
+
+    Ok(picked)
+}
+
+// Whether the config has any schedule rules
+pub fn has_rules(config: &Option<Config>) -> bool {
+    config
+        .as_ref()
+        .and_then(|config| config.schedule.as_ref())
+        .is_some_and(|rules| !rules.is_empty())
+}
+
+// Call `load` with the profiles the schedule picks. With `once` this happens a single time, for
+// running from a systemd timer. Otherwise the schedule is checked every `schedule_interval` seconds
+// and `load` is only called when the pick changes, so switching profiles by hand sticks until then.
+// The config is asked for on every check, so the daemon can follow changes to it.
+pub fn run(once: bool, mut current: impl FnMut() -> Option<Config>, mut load: impl FnMut(Vec<String>)) {
+    let mut loaded: Option<Vec<String>> = None;
+
+    loop {
+        let config = current();
+        let interval = Duration::from_secs(config.as_ref().and_then(|config| config.schedule_interval).unwrap_or(60).max(1));
+
+        match config.as_ref().map_or(Ok(None), pick) {
+            // The errors were reported, a daemon keeps going in case the config gets fixed
+            Err(_) if once => return,
+            Err(_) => {}
+            Ok(Some(profiles)) if loaded.as_ref() != Some(&profiles) => {
+                println!(
+                    "Loading {}\"{}\"{}",
+                    util::color("green", "fg"),
+                    profiles.join("\", \""),
+                    util::color("white", "fg"),
+                );
+                load(profiles.clone());
+                loaded = Some(profiles);
+            }
+            Ok(_) => {}
+        }
+
+        if once {
+            return;
+        }
+
+        std::thread::sleep(interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() <= 2.0, "{} isn't within two minutes of {}", actual, expected);
+    }
+
+    #[test]
+    fn sun_times_of_known_days() {
+        // London on the summer solstice, sunrise 03:43 and sunset 20:21 UTC
+        let london = Location { latitude: 51.5074, longitude: -0.1278 };
+        let (sunrise, sunset) = sun_times(NaiveDate::from_ymd_opt(2024, 6, 21).unwrap(), london).unwrap();
+        assert_close(sunrise, 3.0 * 60.0 + 43.0);
+        assert_close(sunset, 20.0 * 60.0 + 21.0);
+
+        // New York on the winter solstice, sunrise 12:16 and sunset 21:32 UTC
+        let new_york = Location { latitude: 40.7128, longitude: -74.0060 };
+        let (sunrise, sunset) = sun_times(NaiveDate::from_ymd_opt(2024, 12, 21).unwrap(), new_york).unwrap();
+        assert_close(sunrise, 12.0 * 60.0 + 16.0);
+        assert_close(sunset, 21.0 * 60.0 + 32.0);
+    }
+
+    #[test]
+    fn sun_times_during_polar_day_and_night() {
+        let tromso = Location { latitude: 69.6492, longitude: 18.9553 };
+        assert!(sun_times(NaiveDate::from_ymd_opt(2024, 6, 21).unwrap(), tromso).is_none());
+        assert!(sun_times(NaiveDate::from_ymd_opt(2024, 12, 21).unwrap(), tromso).is_none());
+    }
+
+    #[test]
+    fn minutes_with_offsets() {
+        assert_eq!(minutes("08:00", None), Ok(Some(480)));
+        assert_eq!(minutes(" 08:00+15 ", None), Ok(Some(495)));
+        assert_eq!(minutes("08:00-90", None), Ok(Some(390)));
+    }
+
+    #[test]
+    fn minutes_wrap_around_midnight() {
+        assert_eq!(minutes("23:50+20", None), Ok(Some(10)));
+        assert_eq!(minutes("00:10-20", None), Ok(Some(1430)));
+        assert_eq!(minutes("12:00+1440", None), Ok(Some(720)));
+    }
+
+    #[test]
+    fn minutes_rejects_bad_times() {
+        assert!(minutes("24:00", None).is_err());
+        assert!(minutes("8", None).is_err());
+        assert!(minutes("08:00+x", None).is_err());
+        assert!(minutes("sunset-30", None).is_err());
+    }
+}
@@ -1,15 +1,63 @@
-/// Handles the structure of a setting
+//! Handles the structure of a setting
 
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::de::{self, Deserialize, Deserializer, SeqAccess, Visitor};
 use serde_derive::{Serialize, Deserialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+use crate::util;
+use crate::encoding::Encoding;
+
+#[derive(Debug, Serialize, JsonSchema)]
 #[serde(untagged)]
 pub enum Pattern {
     Region([String; 2]),
     Line(String)
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+// Written by hand instead of being untagged, so a region of the wrong length gets a useful error
+// instead of "data did not match any variant"
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct PatternVisitor;
+
+        impl<'de> Visitor<'de> for PatternVisitor {
+            type Value = Pattern;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a regex, or a region as a list of a start and an end regex")
+            }
+
+            fn visit_str<E: de::Error>(self, pattern: &str) -> Result<Self::Value, E> {
+                Ok(Pattern::Line(pattern.to_string()))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut region: Vec<String> = Vec::new();
+
+                while let Some(pattern) = seq.next_element::<String>()? {
+                    region.push(pattern);
+                }
+
+                match <[String; 2]>::try_from(region) {
+                    Ok(region) => Ok(Pattern::Region(region)),
+                    Err(region) => Err(de::Error::custom(format!(
+                        "a region needs exactly 2 regexes, [start, end], got {}",
+                        region.len()
+                    )))
+                }
+            }
+        }
+
+        deserializer.deserialize_any(PatternVisitor)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub enum Replace {
     #[serde(rename = "line_above")]
     LineAbove,
@@ -19,10 +67,12 @@ pub enum Replace {
     MatchedText
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct Setting {
     pub name: String,
-    pub file: String,
+    #[serde(default)]
+    pub file: String, // Can be left out if the registry file has a default for it
     pub pattern: Pattern,
     pub substitute: String,
 
@@ -31,4 +81,246 @@ pub struct Setting {
     pub multiple: Option<bool>, // Default: false
     pub before: Option<String>, // Default: false
     pub after: Option<String>, // Default: false
+    pub encoding: Option<Encoding>, // Default: Utf8
+    pub parameters: Option<Vec<BTreeMap<String, String>>>, // Default: None, makes the setting a template
+    pub when: Option<Condition>, // Default: None, the setting is always used
+    pub order: Option<i64>, // Default: 0, settings with a lower order are applied first
+    pub depends_on: Option<Vec<String>>, // Default: None, settings applied before this one when set together
+
+    #[serde(skip)]
+    pub compiled: Option<Compiled>, // Filled in by `Setting::compile` when the registry is loaded
+    #[serde(skip)]
+    pub source: String, // The registry file the setting was read from
+    #[serde(skip)]
+    pub skipped: bool, // Whether `when` doesn't hold on this machine, filled in when the registry is loaded
+}
+
+// Conditions for using a setting, all the given ones have to hold
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Condition {
+    pub hostname: Option<Vec<String>>, // The hostname is one of these
+    pub env: Option<Vec<String>>, // Each "VAR" is set and not empty, each "VAR=value" is set to value
+    pub file_exists: Option<Vec<String>>, // Each of these paths exists
+    pub command: Option<Vec<String>> // Each of these commands is in $PATH
+}
+
+impl Condition {
+    pub fn holds(&self) -> bool {
+        if let Some(hostnames) = &self.hostname {
+            match util::hostname() {
+                Some(hostname) if hostnames.contains(&hostname) => {}
+                _ => return false
+            }
+        }
+
+        if let Some(variables) = &self.env {
+            let holds = variables.iter().all(|variable| match variable.split_once('=') {
+                Some((name, value)) => std::env::var(name).map(|actual| actual == value).unwrap_or(false),
+                None => std::env::var(variable).map(|actual| !actual.is_empty()).unwrap_or(false)
+            });
+
+            if !holds {
+                return false;
+            }
+        }
+
+        if let Some(paths) = &self.file_exists {
+            if !paths.iter().all(|path| std::path::Path::new(&util::expand_env_vars(path)).exists()) {
+                return false;
+            }
+        }
+
+        if let Some(commands) = &self.command {
+            if !commands.iter().all(|command| util::in_path(command)) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+// The regexes of a setting, compiled once when the registry is loaded
+#[derive(Debug)]
+pub struct Compiled {
+    pub matcher: Matcher,
+    // Built from the substitute, captures the value from the text the matcher selected. None when
+    // the substitute has no "{value}" and always writes the same text.
+    pub value: Option<Regex>
+}
+
+#[derive(Debug)]
+pub enum Matcher {
+    Line(Regex),
+    Region(Regex, Regex)
+}
+
+impl Setting {
+    // Expand a template into one setting for every set of parameters, each "{parameter}" in the
+    // template is replaced by its value. Settings without parameters are returned as they are.
+    pub fn expand(self) -> Result<Vec<Setting>, String> {
+        let parameters = match &self.parameters {
+            Some(parameters) => parameters,
+            None => return Ok(vec![self])
+        };
+
+        let mut settings: Vec<Setting> = Vec::new();
+
+        for values in parameters {
+            if values.contains_key("value") {
+                return Err("\"value\" is reserved and can't be used as a parameter".to_string());
+            }
+
+            let fill = |text: &str| -> String {
+                values
+                    .iter()
+                    .fold(text.to_string(), |text, (name, value)| text.replace(&format!("{{{}}}", name), value))
+            };
+
+            settings.push(Setting {
+                name: fill(&self.name),
+                file: fill(&self.file),
+                pattern: match &self.pattern {
+                    Pattern::Line(pattern) => Pattern::Line(fill(pattern)),
+                    Pattern::Region(region) => Pattern::Region([fill(&region[0]), fill(&region[1])])
+                },
+                substitute: fill(&self.substitute),
+                replace: self.replace.clone(),
+                read_value_path: self.read_value_path,
+                multiple: self.multiple,
+                before: self.before.as_deref().map(fill),
+                after: self.after.as_deref().map(fill),
+                encoding: self.encoding,
+                parameters: None,
+                when: self.when.clone(),
+                order: self.order,
+                depends_on: self.depends_on.as_ref().map(|names| names.iter().map(|name| fill(name)).collect()),
+                compiled: None,
+                source: self.source.to_string(),
+                skipped: false
+            });
+        }
+
+        Ok(settings)
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding.unwrap_or_default()
+    }
+
+    pub fn multiple(&self) -> bool {
+        self.multiple.unwrap_or(false)
+    }
+
+    pub fn order(&self) -> i64 {
+        self.order.unwrap_or(0)
+    }
+
+    // Compile the pattern and the value regex, returning every error instead of only the first
+    pub fn compile(&mut self) -> Result<(), Vec<String>> {
+        let mut errors: Vec<String> = Vec::new();
+
+        let mut compile = |field: &str, pattern: &str| match Regex::new(pattern) {
+            Ok(regex) => Some(regex),
+            Err(error) => {
+                errors.push(format!("{}: {}", field, error));
+                None
+            }
+        };
+
+        let matcher = match &self.pattern {
+            Pattern::Line(pattern) => compile("pattern", pattern).map(Matcher::Line),
+            Pattern::Region(region) => {
+                let start = compile("pattern[0]", &region[0]);
+                let end = compile("pattern[1]", &region[1]);
+
+                match (start, end) {
+                    (Some(start), Some(end)) => Some(Matcher::Region(start, end)),
+                    _ => None
+                }
+            }
+        };
+
+        let value = if self.substitute.contains("{value}") {
+            compile("substitute", &value_regex(&self.substitute)).map(Some)
+        } else {
+            Some(None)
+        };
+
+        match (matcher, value) {
+            (Some(matcher), Some(value)) => {
+                self.compiled = Some(Compiled { matcher, value });
+                Ok(())
+            }
+            _ => Err(errors)
+        }
+    }
+
+    pub fn compiled(&self) -> &Compiled {
+        self.compiled
+            .as_ref()
+            .expect("settings are compiled when the registry is loaded")
+    }
+}
+
+// Turn a substitute into a regex that captures the first "{value}" and treats the rest literally
+fn value_regex(substitute: &str) -> String {
+    let parts: Vec<String> = substitute
+        .split("{value}")
+        .map(regex::escape)
+        .collect();
+
+    // Match all characters, even new lines
+    let mut built = parts[0].clone();
+    built.push_str("((?s:.*))");
+    built.push_str(&parts[1..].join("(?s:.*)"));
+    built
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extract(substitute: &str, text: &str) -> Option<String> {
+        let regex = Regex::new(&value_regex(substitute)).unwrap();
+        regex.captures(text).map(|captures| captures[1].to_string())
+    }
+
+    #[test]
+    fn value_regex_extracts_from_a_line() {
+        assert_eq!(extract("font = {value}", "font = Mono 12").as_deref(), Some("Mono 12"));
+        assert_eq!(extract("font = {value}", "size = 12"), None);
+    }
+
+    #[test]
+    fn value_regex_stops_before_the_suffix() {
+        assert_eq!(extract("color: {value};", "color: #ffffff;").as_deref(), Some("#ffffff"));
+        assert_eq!(extract("set $gap ({value}) # px", "set $gap (12) # px").as_deref(), Some("12"));
+    }
+
+    #[test]
+    fn value_regex_extracts_from_a_region() {
+        let substitute = "[colors]\nforeground = {value}\nbackground = #000000";
+        let text = "[colors]\nforeground = #ffffff\nbackground = #000000";
+
+        assert_eq!(extract(substitute, text).as_deref(), Some("#ffffff"));
+        assert_eq!(extract("[colors]\n{value}\n[end]", "[colors]\na = 1\nb = 2\n[end]").as_deref(), Some("a = 1\nb = 2"));
+    }
+
+    #[test]
+    fn value_regex_captures_the_first_of_several_values() {
+        assert_eq!(extract("gaps {value} {value}", "gaps 10 10").as_deref(), Some("10"));
+        assert_eq!(extract("{value}x{value}", "1920x1920").as_deref(), Some("1920"));
+    }
+
+    #[test]
+    fn compile_accepts_a_substitute_without_value() {
+        let mut setting: Setting = serde_json::from_str(
+            r#"{ "name": "bar", "file": "~/.bashrc", "pattern": "^bar=", "substitute": "bar=on" }"#
+        ).unwrap();
+
+        assert!(setting.compile().is_ok());
+        assert!(setting.compiled().value.is_none());
+    }
 }
@@ -0,0 +1,95 @@
+//! Handles generating whole files from templates filled with setting values
+
+use regex::Regex;
+use schemars::JsonSchema;
+use serde_derive::{Serialize, Deserialize};
+
+use crate::util;
+use crate::encoding::{self, Encoding};
+use crate::functions;
+use crate::setting::Setting;
+
+// A file rendered from a template whenever a setting it references changes. "{{name}}" in the
+// template is replaced by the value of the setting "name".
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Template {
+    pub source: String, // Relative paths are resolved against the registry file
+    pub target: String, // Relative paths are resolved against the registry file
+    pub encoding: Option<Encoding>, // Default: Utf8
+
+    #[serde(skip)]
+    pub origin: String, // The registry file the template was read from
+}
+
+fn placeholder() -> Regex {
+    Regex::new(r"\{\{\s*([^{}\s]+)\s*\}\}").unwrap()
+}
+
+// The names of the settings a template references
+pub fn references(contents: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+
+    for captures in placeholder().captures_iter(contents) {
+        if !names.contains(&captures[1].to_string()) {
+            names.push(captures[1].to_string());
+        }
+    }
+
+    names
+}
+
+// Render one template, the target is only written if its contents change
+fn render(template: &Template, registry: &[Setting]) -> Result<(), String> {
+    let encoding = template.encoding.unwrap_or_default();
+    let contents = encoding::read(&template.source, encoding)?;
+
+    let names = references(&contents);
+    let values = functions::get_values(names.clone(), registry);
+
+    if let Some(missing) = names.iter().find(|name| !values.iter().any(|(value_name, _)| value_name == *name)) {
+        return Err(format!("No value for \"{}\"", missing));
+    }
+
+    let rendered = placeholder().replace_all(&contents, |captures: &regex::Captures| {
+        values
+            .iter()
+            .find(|(name, _)| *name == captures[1])
+            .map(|(_, value)| value.to_string())
+            .unwrap_or_default()
+    });
+
+    if encoding::read(&template.target, encoding).ok().as_deref() == Some(&rendered) {
+        return Ok(());
+    }
+
+    encoding::write(&template.target, &rendered, encoding)
+}
+
+// Render the templates that reference one of the changed settings, or every template if `changed`
+// is None
+pub fn render_changed(changed: Option<&[String]>, templates: &[Template], registry: &[Setting]) {
+    for template in templates {
+        // A source that can't be read isn't skipped, rendering it reports the error
+        if let (Some(changed), Ok(contents)) = (changed, encoding::read(&template.source, template.encoding.unwrap_or_default())) {
+            if !references(&contents).iter().any(|name| changed.contains(name)) {
+                continue;
+            }
+        }
+
+        if let Err(error) = render(template, registry) {
+            errln!(
+                "Failed to render template {}\"{}\"{} to {}\"{}\"{}: {}{}{}",
+                util::color("green", "fg"),
+                template.source,
+                util::color("white", "fg"),
+                util::color("green", "fg"),
+                template.target,
+                util::color("white", "fg"),
+                util::color("red", "fg"),
+                error,
+                util::color("white", "fg"),
+            );
+        }
+    }
+}
@@ -1,17 +1,14 @@
-/// This file contains functions that assists development
+//! This file contains functions that assists development
 
-use shellexpand; 
+use std::cell::RefCell;
 
-pub fn expand_env_vars(string: &str) -> String {
-    let expanded = shellexpand::full(string);
+use serde_derive::{Serialize, Deserialize};
 
-    if expanded.is_ok() {
-        return expanded
-            .unwrap()
-            .to_string();
+pub fn expand_env_vars(string: &str) -> String {
+    match shellexpand::full(string) {
+        Ok(expanded) => expanded.to_string(),
+        Err(_) => string.to_string()
     }
-    
-    string.to_string()
 }
 
 pub fn color<'a>(color: &'a str, ground: &'a str) -> &'a str {
@@ -63,3 +60,131 @@ pub fn color<'a>(color: &'a str, ground: &'a str) -> &'a str {
     }
 }
 
+
+// The exconman config directory, "$XDG_CONFIG_HOME/exconman" or "~/.config/exconman"
+pub fn config_dir() -> String {
+    match std::env::var("XDG_CONFIG_HOME") {
+        Ok(config_home) if config_home.starts_with('/') => format!("{}/exconman", config_home),
+        _ => expand_env_vars("~/.config/exconman")
+    }
+}
+
+// The exconman state directory, "$XDG_STATE_HOME/exconman" or "~/.local/state/exconman"
+pub fn state_dir() -> String {
+    match std::env::var("XDG_STATE_HOME") {
+        Ok(state_home) if state_home.starts_with('/') => format!("{}/exconman", state_home),
+        _ => expand_env_vars("~/.local/state/exconman")
+    }
+}
+
+pub fn hostname() -> Option<String> {
+    ["/proc/sys/kernel/hostname", "/etc/hostname"]
+        .iter()
+        .filter_map(|path| std::fs::read_to_string(path).ok())
+        .map(|hostname| hostname.trim().to_string())
+        .find(|hostname| !hostname.is_empty())
+}
+
+// Whether an executable with this name is in one of the directories of $PATH
+pub fn in_path(command: &str) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = match std::env::var_os("PATH") {
+        Some(path) => path,
+        None => return false
+    };
+
+    std::env::split_paths(&path).any(|dir| {
+        std::fs::metadata(dir.join(command))
+            .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+            .unwrap_or(false)
+    })
+}
+
+// Suggest the closest match for an unknown field or variant in a serde error message, returns an
+// empty string if there's nothing to suggest
+pub fn did_you_mean(message: &str) -> String {
+    let rgx = regex::Regex::new(r"unknown (?:field|variant) `([^`]*)`, expected (.*)").unwrap();
+
+    let captures = match rgx.captures(message) {
+        Some(captures) => captures,
+        None => return String::new()
+    };
+
+    let unknown = &captures[1];
+    let expected: Vec<&str> = captures[2]
+        .split('`')
+        .skip(1)
+        .step_by(2)
+        .collect();
+
+    let closest = expected
+        .iter()
+        .map(|candidate| (strsim::damerau_levenshtein(unknown, candidate), candidate))
+        .filter(|(distance, candidate)| *distance <= std::cmp::max(2, candidate.len() / 3))
+        .min_by_key(|(distance, _)| *distance);
+
+    match closest {
+        Some((_, candidate)) => format!(
+            ", did you mean {}\"{}\"{}?",
+            color("blue", "fg"),
+            candidate,
+            color("white", "fg")
+        ),
+        None => String::new()
+    }
+}
+
+// A line a command printed, to stdout or to stderr
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Output {
+    Stdout(String),
+    Stderr(String)
+}
+
+thread_local! {
+    // The lines printed while `capture` runs on this thread, None the rest of the time
+    static CAPTURED: RefCell<Option<Vec<Output>>> = const { RefCell::new(None) };
+}
+
+// Print a line, or keep it if `capture` is running on this thread
+pub fn emit(output: Output) {
+    let output = CAPTURED.with(|captured| match captured.borrow_mut().as_mut() {
+        Some(lines) => {
+            lines.push(output);
+            None
+        }
+        None => Some(output)
+    });
+
+    match output {
+        Some(Output::Stdout(line)) => println!("{}", line),
+        Some(Output::Stderr(line)) => eprintln!("{}", line),
+        None => {}
+    }
+}
+
+// Run `f` and return the lines it printed through `outln!` and `errln!` instead of printing them,
+// so the daemon can send them to the command it runs them for
+pub fn capture<T>(f: impl FnOnce() -> T) -> (T, Vec<Output>) {
+    CAPTURED.with(|captured| *captured.borrow_mut() = Some(Vec::new()));
+    let result = f();
+    let lines = CAPTURED.with(|captured| captured.borrow_mut().take()).unwrap_or_default();
+
+    (result, lines)
+}
+
+// println! for the commands the daemon runs, see `capture`
+macro_rules! outln {
+    ($($arg:tt)*) => {
+        $crate::util::emit($crate::util::Output::Stdout(format!($($arg)*)))
+    };
+}
+
+// eprintln! for the commands the daemon runs, see `capture`
+macro_rules! errln {
+    ($($arg:tt)*) => {
+        $crate::util::emit($crate::util::Output::Stderr(format!($($arg)*)))
+    };
+}
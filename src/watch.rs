@@ -0,0 +1,163 @@
+//! Handles watching the files of the registry for values drifting from the active profile
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use inotify::{Inotify, WatchDescriptor, WatchMask};
+use schemars::JsonSchema;
+use serde_derive::{Serialize, Deserialize};
+
+use crate::util;
+use crate::profile;
+use crate::functions;
+use crate::config::Config;
+use crate::registry::Registry;
+
+// What `exconman watch` does when a value no longer matches the active profile
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum Drift {
+    #[serde(rename = "log")]
+    Log,
+    #[serde(rename = "reapply")]
+    Reapply
+}
+
+// Compare the settings of changed files against the active profile, and report or re-apply the
+// values that drifted
+fn check(files: &[String], config: &Option<Config>, registry: &Registry) {
+    let active = profile::current();
+
+    if active.is_empty() {
+        return;
+    }
+
+    let expected = match profile::read(&active) {
+        Ok(expected) => expected,
+        Err(_) => return
+    };
+
+    let names: Vec<String> = registry.settings
+        .iter()
+        .filter(|setting| !setting.skipped && expected.contains_key(&setting.name))
+        .filter(|setting| files.contains(&util::expand_env_vars(&setting.file)))
+        .map(|setting| setting.name.to_string())
+        .collect();
+
+    let drifted: Vec<(String, String)> = functions::get_values(names, &registry.settings)
+        .into_iter()
+        .filter(|(name, value)| expected.get(name) != Some(value))
+        .collect();
+
+    if drifted.is_empty() {
+        return;
+    }
+
+    for (name, value) in &drifted {
+        println!(
+            "{}\"{}\"{} drifted from {}\"{}\"{} to {}\"{}\"{}",
+            util::color("green", "fg"),
+            name,
+            util::color("white", "fg"),
+            util::color("green", "fg"),
+            expected[name],
+            util::color("white", "fg"),
+            util::color("yellow", "fg"),
+            value,
+            util::color("white", "fg"),
+        );
+    }
+
+    let drift = config.as_ref().and_then(|config| config.on_drift).unwrap_or(Drift::Log);
+
+    if drift == Drift::Reapply {
+        let values: Vec<(String, String)> = drifted
+            .into_iter()
+            .map(|(name, _)| (name.to_string(), expected[&name].to_string()))
+            .collect();
+
+        functions::set_values(values, false, config, registry);
+    }
+}
+
+// Watch every file the registry refers to and check it against the active profile whenever it's
+// written. The directories are watched rather than the files, since programs often replace a file
+// instead of writing to it.
+pub fn watch(config: &Option<Config>, registry: &Registry) {
+    let report = |error: std::io::Error| {
+        eprintln!(
+            "Failed to watch files: {}{}{}",
+            util::color("red", "fg"),
+            error,
+            util::color("white", "fg"),
+        );
+    };
+
+    let mut inotify = match Inotify::init() {
+        Ok(inotify) => inotify,
+        Err(error) => return report(error)
+    };
+
+    let mut files: Vec<String> = registry.settings
+        .iter()
+        .filter(|setting| !setting.skipped)
+        .map(|setting| util::expand_env_vars(&setting.file))
+        .collect();
+
+    files.sort();
+    files.dedup();
+
+    let mut dirs: BTreeMap<PathBuf, Option<WatchDescriptor>> = files
+        .iter()
+        .filter_map(|file| PathBuf::from(file).parent().map(|dir| (dir.to_path_buf(), None)))
+        .collect();
+
+    for (dir, descriptor) in dirs.iter_mut() {
+        match inotify.watches().add(dir, WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO | WatchMask::CREATE) {
+            Ok(added) => *descriptor = Some(added),
+            Err(error) => eprintln!(
+                "Failed to watch {}\"{}\"{}: {}{}{}",
+                util::color("green", "fg"),
+                dir.display(),
+                util::color("white", "fg"),
+                util::color("red", "fg"),
+                error,
+                util::color("white", "fg"),
+            )
+        }
+    }
+
+    if profile::current().is_empty() {
+        eprintln!(
+            "No profile is active, changes are only checked once one is loaded with {}exconman load{}",
+            util::color("blue", "fg"),
+            util::color("white", "fg"),
+        );
+    }
+
+    let mut buffer = [0; 4096];
+
+    loop {
+        let events = match inotify.read_events_blocking(&mut buffer) {
+            Ok(events) => events,
+            Err(error) => return report(error)
+        };
+
+        let mut changed: Vec<String> = Vec::new();
+
+        for event in events {
+            let dir = dirs.iter().find(|(_, descriptor)| descriptor.as_ref() == Some(&event.wd));
+
+            if let (Some((dir, _)), Some(name)) = (dir, event.name) {
+                let path = dir.join(name).display().to_string();
+
+                if files.contains(&path) && !changed.contains(&path) {
+                    changed.push(path);
+                }
+            }
+        }
+
+        if !changed.is_empty() {
+            check(&changed, config, registry);
+        }
+    }
+}